@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
@@ -5,37 +7,94 @@ use std::path::PathBuf;
 use anyhow::{anyhow, Context};
 use pico_args::Arguments;
 use shitty_file_format::FileStructure;
-use shitty_parser;
+use shitty_runtime::{
+    run_together, BlockStorage, Channels, FileSystemPolicy, Framebuffer, RunOutcome, Runtime,
+    Scheduler, Timer, BLOCK_STORAGE_ADDRESS, DEFAULT_QUANTUM, FRAMEBUFFER_ADDRESS,
+};
+use shitty_types::{Integer, Program, Radix};
 use std::process::ExitCode;
 
+/// Exit status when a program is stopped by --limit, the same as timeout(1) uses.
+const LIMIT_REACHED_STATUS: u8 = 124;
+
 const HELP_MESSAGE: &str = r#"
     Usage: shitty_cli <subcommand>
 
     Subcommands:
 
-    run [options] <program>
+    run [options] <program> [-- <args>...]
         options:
             -o, --open <file>
+            -I, --include <dir> : search dir for .include files, can be repeated
+            -D, --define <name=value> : define a constant for .if and expressions, can be repeated
+            -i, --input <file> : read program input from a file instead of stdin
+            --allow-read <dir> : allow the program to read files in dir, can be repeated
+            --allow-write <dir> : allow the program to write files in dir, can be repeated
+            --allow-env <name> : let the program read the environment variable name with getenv, can be repeated
             --output-as-status-code : return the output as statuscode
+            --cycles : report the number of cycles used
+            --heap-stats : report heap allocations and leaks
+            --limit <n> : stop after executing n instructions
+            --timer <n> : raise the timer interrupt every n instructions
+            --seed <n> : interleave spawned cores in a pseudo-random order from seed n
+            --frames <dir> : write every flushed framebuffer frame to dir as a PPM image
+            --disk <image> : attach the image file as block storage device
+
+    run-many [options] <file>...
+        runs the programs together, they can talk over channels with the send and recv functions
+        exits with the status of the first program that did not halt, like run
+        options:
+            -I, --include <dir> : search dir for .include files, can be repeated
+            -D, --define <name=value> : define a constant for .if and expressions, can be repeated
+            --limit <n> : stop each program after executing n instructions
+
+    compile [options] <input_file> <output_file>
+        options:
+            -I, --include <dir> : search dir for .include files, can be repeated
+            -D, --define <name=value> : define a constant for .if and expressions, can be repeated
 
-    compile <input_file> <output_file>
+    disassemble [options] <file>
+        prints a compiled program, with the label and constant names from its source
+        and where its data is put on the heap
+        options:
+            --radix <bin|oct|dec|hex> : write numbers in this base, dec by default
     
-    exec [options] <file>
+    exec [options] <file> [-- <args>...]
         options:
+            -i, --input <file> : read program input from a file instead of stdin
+            --allow-read <dir> : allow the program to read files in dir, can be repeated
+            --allow-write <dir> : allow the program to write files in dir, can be repeated
+            --allow-env <name> : let the program read the environment variable name with getenv, can be repeated
             --output-as-status-code : return the output as statuscode
+            --cycles : report the number of cycles used
+            --heap-stats : report heap allocations and leaks
+            --limit <n> : stop after executing n instructions
+            --timer <n> : raise the timer interrupt every n instructions
+            --seed <n> : interleave spawned cores in a pseudo-random order from seed n
+            --frames <dir> : write every flushed framebuffer frame to dir as a PPM image
+            --disk <image> : attach the image file as block storage device
+
+    Arguments after `--` are passed to the program, see the argc and argv functions.
+
+    Exit status of run and exec:
+        the code given to `exit`, 1 when the program traps,
+        124 when --limit is reached, otherwise 0 or the output with --output-as-status-code
 "#;
 
 fn main() -> Result<ExitCode, anyhow::Error> {
-    let mut args = Arguments::from_env();
+    let (cli_args, program_args) = split_program_args(std::env::args_os().skip(1).collect())?;
+    let mut args = Arguments::from_vec(cli_args);
 
     if args.contains(["-h", "--help"]) {
         return help();
     }
 
     match args.subcommand() {
-        Ok(Some(x)) if x == "run" => run(&mut args),
+        Ok(Some(x)) if x == "run" => run(&mut args, program_args),
+        Ok(Some(x)) if x == "run-many" => run_many(&mut args),
         Ok(Some(x)) if x == "compile" => compile(&mut args),
-        Ok(Some(x)) if x == "exec" => exec(&mut args),
+        Ok(Some(x)) if x == "exec" => exec(&mut args, program_args),
+        Ok(Some(x)) if x == "disassemble" => disassemble(&mut args),
         Ok(Some(x)) if x == "script" => script(&mut args),
         Ok(Some(x)) if x == "help" => help(),
         _ => {
@@ -44,72 +103,324 @@ fn main() -> Result<ExitCode, anyhow::Error> {
     }
 }
 
+/// Splits off the arguments after `--`, these belong to the program and not to the cli.
+fn split_program_args(
+    mut args: Vec<OsString>,
+) -> Result<(Vec<OsString>, Vec<String>), anyhow::Error> {
+    let Some(index) = args.iter().position(|arg| arg == "--") else {
+        return Ok((args, Vec::new()));
+    };
+    let program_args = args
+        .split_off(index + 1)
+        .into_iter()
+        .map(|arg| arg.into_string())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|arg| anyhow!("program argument is not valid unicode: {arg:?}"))?;
+    args.pop();
+
+    Ok((args, program_args))
+}
+
 fn help() -> Result<ExitCode, anyhow::Error> {
     eprintln!("{}", HELP_MESSAGE);
     Ok(ExitCode::SUCCESS)
 }
 
-fn run(args: &mut Arguments) -> Result<ExitCode, anyhow::Error> {
+fn run(args: &mut Arguments, program_args: Vec<String>) -> Result<ExitCode, anyhow::Error> {
     let file: Option<PathBuf> = args.opt_value_from_str(["-o", "--open"])?;
-    let output_as_status_code = args.contains("--output-as-status-code");
-    let debug = args.contains("--debug");
+    let assembler_options = assembler_options(args)?;
+    let options = RunOptions::from_args(args)?;
     let program_text: Option<String> = args.opt_free_from_str()?;
 
-    let program = match (file, program_text) {
+    let (program, _) = match (file, program_text) {
         (Some(_), Some(_)) => return Err(anyhow!("Cannot specify both -o and a file")),
         (None, None) => return Err(anyhow!("Must specify either -o or a file")),
-        (Some(path), None) => {
-            let mut file = File::open(path)?;
-            let input = BufReader::new(&mut file);
-            shitty_parser::parse(input).map_err(|e| anyhow::anyhow!("{}", e))?
-        }
-        (None, Some(input)) => {
-            shitty_parser::parse_from_str(&input).map_err(|e| anyhow::anyhow!("{}", e))?
+        (Some(path), None) => shitty_parser::assemble_file(path, &assembler_options),
+        (None, Some(input)) => shitty_parser::assemble_with(input.as_bytes(), &assembler_options),
+    }
+    .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    execute(program, options, program_args)
+}
+
+fn run_many(args: &mut Arguments) -> Result<ExitCode, anyhow::Error> {
+    let assembler_options = assembler_options(args)?;
+    let limit: Option<Integer> = args.opt_value_from_str("--limit")?;
+    let mut paths = Vec::new();
+    while let Some(path) = args.opt_free_from_str::<PathBuf>()? {
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        return Err(anyhow!("Must specify at least one file"));
+    }
+
+    let channels = Channels::default();
+    let mut runtimes = Vec::new();
+    for path in paths.iter() {
+        let (program, _) = shitty_parser::assemble_file(path, &assembler_options)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let mut rt = Runtime::new(program).with_channels(channels.clone());
+        if let Some(limit) = limit {
+            rt = rt.with_instruction_limit(limit);
         }
-    };
+        runtimes.push(rt);
+    }
 
-    let mut rt = shitty_runtime::Runtime::new(program).with_debug(debug);
-    rt.run().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let outcomes =
+        run_together(&mut runtimes, DEFAULT_QUANTUM).map_err(|e| anyhow::anyhow!("{}", e))?;
 
-    if output_as_status_code {
-        let status: u8 = rt.output().try_into().context("parsing status code")?;
-        return Ok(ExitCode::from(status));
+    let mut status = 0;
+    for ((path, rt), outcome) in paths.iter().zip(runtimes.iter()).zip(outcomes) {
+        let program_status = match outcome {
+            RunOutcome::LimitReached => {
+                eprintln!("{}: instruction limit reached", path.display());
+                LIMIT_REACHED_STATUS
+            }
+            RunOutcome::Trapped(_, message) => {
+                eprintln!("{}: {}", path.display(), message);
+                1
+            }
+            RunOutcome::Exited(code) => {
+                println!("{}: {}", path.display(), rt.output());
+                code.try_into().context("parsing exit code")?
+            }
+            RunOutcome::Halted | RunOutcome::FellOffEnd => {
+                println!("{}: {}", path.display(), rt.output());
+                0
+            }
+        };
+        if status == 0 {
+            status = program_status;
+        }
     }
-    println!("{}", rt.output());
 
-    Ok(ExitCode::SUCCESS)
+    Ok(ExitCode::from(status))
 }
 
 fn compile(args: &mut Arguments) -> Result<ExitCode, anyhow::Error> {
+    let options = assembler_options(args)?;
     let input_path: PathBuf = args.free_from_str()?;
     let output_path: PathBuf = args.free_from_str()?;
 
-    let mut in_file = File::open(input_path)?;
-    let input = BufReader::new(&mut in_file);
-    let program = shitty_parser::parse(input).map_err(|e| anyhow::anyhow!("{}", e))?;
+    let (program, symbols) =
+        shitty_parser::assemble_file(input_path, &options).map_err(|e| anyhow::anyhow!("{}", e))?;
 
-    let file = FileStructure::new(program);
+    let file = FileStructure::new(program).with_symbols(symbols);
     file.to_path(output_path)
         .map_err(|e| anyhow::anyhow!("{}", e))?;
 
     Ok(ExitCode::SUCCESS)
 }
 
-fn exec(args: &mut Arguments) -> Result<ExitCode, anyhow::Error> {
-    let output_as_status_code = args.contains("--output-as-status-code");
+fn exec(args: &mut Arguments, program_args: Vec<String>) -> Result<ExitCode, anyhow::Error> {
+    let options = RunOptions::from_args(args)?;
     let file_path: PathBuf = args.free_from_str()?;
 
     let file = FileStructure::from_path(file_path).map_err(|e| anyhow::anyhow!("{}", e))?;
-    let mut rt = shitty_runtime::Runtime::new(file.program);
-    rt.run().map_err(|e| anyhow::anyhow!("{}", e))?;
-    if output_as_status_code {
+    execute(file.program, options, program_args)
+}
+
+fn disassemble(args: &mut Arguments) -> Result<ExitCode, anyhow::Error> {
+    let radix: Option<Radix> = args.opt_value_from_str("--radix")?;
+    let file_path: PathBuf = args.free_from_str()?;
+
+    let file = FileStructure::from_path(file_path).map_err(|e| anyhow::anyhow!("{}", e))?;
+    print!("{}", format_layout(&file));
+    print!(
+        "{}",
+        shitty_types::format_program_with_symbols(
+            &file.program,
+            &file.symbols,
+            radix.unwrap_or_default()
+        )
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The heap entry of every data label as comments, like `; heap 0: message, 6 words, line 3`.
+fn format_layout(file: &FileStructure) -> String {
+    file.layout
+        .iter()
+        .map(|entry| {
+            format!(
+                "; heap {}: {}, {} words, line {}\n",
+                entry.handle,
+                file.symbols.label_name(entry.label),
+                entry.length,
+                entry.line
+            )
+        })
+        .collect()
+}
+
+/// Options shared by the subcommands that run a program.
+struct RunOptions {
+    output_as_status_code: bool,
+    debug: bool,
+    report_cycles: bool,
+    report_heap: bool,
+    limit: Option<Integer>,
+    timer: Option<u64>,
+    seed: Option<u64>,
+    frames: Option<PathBuf>,
+    disk: Option<PathBuf>,
+    input: Option<BufReader<File>>,
+    file_system: FileSystemPolicy,
+    environment: BTreeMap<String, String>,
+}
+
+impl RunOptions {
+    fn from_args(args: &mut Arguments) -> Result<Self, anyhow::Error> {
+        Ok(RunOptions {
+            output_as_status_code: args.contains("--output-as-status-code"),
+            debug: args.contains("--debug"),
+            report_cycles: args.contains("--cycles"),
+            report_heap: args.contains("--heap-stats"),
+            limit: args.opt_value_from_str("--limit")?,
+            timer: args.opt_value_from_str("--timer")?,
+            seed: args.opt_value_from_str("--seed")?,
+            frames: args.opt_value_from_str("--frames")?,
+            disk: args.opt_value_from_str("--disk")?,
+            input: program_input(args)?,
+            file_system: file_system_policy(args)?,
+            environment: allowed_environment(args)?,
+        })
+    }
+}
+
+fn execute(
+    program: Program,
+    options: RunOptions,
+    program_args: Vec<String>,
+) -> Result<ExitCode, anyhow::Error> {
+    let mut rt = Runtime::new(program)
+        .with_debug(options.debug)
+        .with_args(program_args)
+        .with_file_system(options.file_system)
+        .with_env(options.environment);
+    if let Some(input) = options.input {
+        rt = rt.with_input(input);
+    }
+    if let Some(limit) = options.limit {
+        rt = rt.with_instruction_limit(limit);
+    }
+    if let Some(interval) = options.timer {
+        rt = rt.with_timer(Timer::new(interval));
+    }
+    if let Some(seed) = options.seed {
+        rt = rt.with_scheduler(Scheduler::seeded(DEFAULT_QUANTUM, seed));
+    }
+    if let Some(path) = options.disk {
+        let disk = BlockStorage::open(path).map_err(|e| anyhow::anyhow!("{}", e))?;
+        rt = rt.with_device(BLOCK_STORAGE_ADDRESS, Box::new(disk));
+    }
+    let framebuffer = options.frames.map(frame_writer).transpose()?;
+    if let Some(framebuffer) = &framebuffer {
+        rt = rt.with_device(FRAMEBUFFER_ADDRESS, Box::new(framebuffer.clone()));
+    }
+
+    let outcome = rt.run();
+    // whatever was drawn after the last flush is written as the final frame
+    if let Some(framebuffer) = framebuffer.filter(Framebuffer::dirty) {
+        framebuffer.flush().map_err(|e| anyhow::anyhow!("{}", e))?;
+    }
+    let outcome = outcome.map_err(|e| anyhow::anyhow!("{}", e))?;
+    if options.report_cycles {
+        eprintln!("cycles: {}", rt.cycles());
+    }
+    if options.report_heap {
+        print_heap_report(&rt);
+    }
+
+    match outcome {
+        RunOutcome::Exited(code) => {
+            let status: u8 = code.try_into().context("parsing exit code")?;
+            return Ok(ExitCode::from(status));
+        }
+        RunOutcome::LimitReached => {
+            eprintln!("instruction limit reached");
+            return Ok(ExitCode::from(LIMIT_REACHED_STATUS));
+        }
+        RunOutcome::Trapped(_, message) => anyhow::bail!("{message}"),
+        RunOutcome::Halted | RunOutcome::FellOffEnd => (),
+    }
+
+    if options.output_as_status_code {
         let status: u8 = rt.output().try_into().context("parsing status code")?;
         return Ok(ExitCode::from(status));
     }
     println!("{}", rt.output());
+
     Ok(ExitCode::SUCCESS)
 }
 
+fn assembler_options(args: &mut Arguments) -> Result<shitty_parser::Options, anyhow::Error> {
+    let mut options = shitty_parser::Options::default();
+    for directory in args.values_from_str::<_, PathBuf>(["-I", "--include"])? {
+        options = options.with_include_path(directory);
+    }
+    for (name, value) in args.values_from_fn(["-D", "--define"], shitty_parser::parse_define)? {
+        options = options.with_define(name, value);
+    }
+    Ok(options)
+}
+
+fn program_input(args: &mut Arguments) -> Result<Option<BufReader<File>>, anyhow::Error> {
+    let path: Option<PathBuf> = args.opt_value_from_str(["-i", "--input"])?;
+    path.map(|path| {
+        let file = File::open(&path).with_context(|| format!("opening {}", path.display()))?;
+        Ok(BufReader::new(file))
+    })
+    .transpose()
+}
+
+fn file_system_policy(args: &mut Arguments) -> Result<FileSystemPolicy, anyhow::Error> {
+    let mut policy = FileSystemPolicy::default();
+    for directory in args.values_from_str::<_, PathBuf>("--allow-read")? {
+        policy = policy.allow_read(directory).map_err(|e| anyhow!("{}", e))?;
+    }
+    for directory in args.values_from_str::<_, PathBuf>("--allow-write")? {
+        policy = policy
+            .allow_write(directory)
+            .map_err(|e| anyhow!("{}", e))?;
+    }
+    Ok(policy)
+}
+
+/// The allowed environment variables that are set, nothing else is passed to the program.
+fn allowed_environment(args: &mut Arguments) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let names: Vec<String> = args.values_from_str("--allow-env")?;
+    Ok(names
+        .into_iter()
+        .filter_map(|name| std::env::var(&name).ok().map(|value| (name, value)))
+        .collect())
+}
+
+/// A framebuffer that writes every flushed frame into `directory`.
+fn frame_writer(directory: PathBuf) -> Result<Framebuffer, anyhow::Error> {
+    std::fs::create_dir_all(&directory)
+        .with_context(|| format!("creating {}", directory.display()))?;
+    Ok(Framebuffer::default().on_flush(move |index, image| {
+        let path = directory.join(format!("frame-{index:04}.ppm"));
+        std::fs::write(&path, image.to_ppm())
+            .map_err(|e| format!("writing {}: {e}", path.display()))
+    }))
+}
+
+fn print_heap_report(rt: &Runtime) {
+    let stats = rt.heap_stats();
+    eprintln!(
+        "heap: {} allocations, {} reallocations, {} frees, peak {} live",
+        stats.allocations, stats.reallocations, stats.frees, stats.peak_live
+    );
+    for leak in rt.leaks() {
+        eprintln!(
+            "leak: heap entry {} (:{}) with {} words",
+            leak.handle, leak.label, leak.length
+        );
+    }
+}
+
 fn script(args: &mut Arguments) -> Result<ExitCode, anyhow::Error> {
     let output_as_status_code = args.contains("--output-as-status-code");
     let file_path: PathBuf = args.free_from_str()?;
@@ -135,15 +446,14 @@ fn script(args: &mut Arguments) -> Result<ExitCode, anyhow::Error> {
 #[test]
 fn run_from_text() {
     let mut args = Arguments::from_vec(vec!["mov r0 #24".into()]);
-    assert!(run(&mut args).is_ok());
+    assert!(run(&mut args, Vec::new()).is_ok());
 
     let mut args = Arguments::from_vec(vec!["invalid r0 #24".into()]);
-    assert!(run(&mut args).is_err());
+    assert!(run(&mut args, Vec::new()).is_err());
 }
 
 #[test]
 fn run_from_file() {
-    use std::ffi::OsString;
     use std::io::Write;
 
     let dir = tempfile::tempdir().unwrap();
@@ -153,7 +463,218 @@ fn run_from_file() {
     writeln!(file, "mov r0 #94").unwrap();
 
     let mut args = Arguments::from_vec(vec!["-o".into(), OsString::from(&file_path)]);
-    assert!(run(&mut args).is_ok());
+    assert!(run(&mut args, Vec::new()).is_ok());
     let mut args = Arguments::from_vec(vec!["--open".into(), OsString::from(file_path)]);
-    assert!(run(&mut args).is_ok());
+    assert!(run(&mut args, Vec::new()).is_ok());
+}
+
+#[test]
+fn compile_and_disassemble() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("constants.s");
+    let binary = dir.path().join("constants.bin");
+    std::fs::write(&source, ".equ LIMIT 8\ncmp r5 #LIMIT").unwrap();
+
+    let mut args = Arguments::from_vec(vec![OsString::from(&source), OsString::from(&binary)]);
+    compile(&mut args).unwrap();
+
+    let file = FileStructure::from_path(&binary).unwrap();
+    assert_eq!(
+        ".equ LIMIT 8\n    cmp r5 #LIMIT\n",
+        shitty_types::format_program_with_symbols(&file.program, &file.symbols, Radix::Decimal)
+    );
+    let mut args = Arguments::from_vec(vec![
+        "--radix".into(),
+        "hex".into(),
+        OsString::from(&binary),
+    ]);
+    assert!(disassemble(&mut args).is_ok());
+}
+
+#[test]
+fn compile_with_sections() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("sections.s");
+    let binary = dir.path().join("sections.bin");
+    std::fs::write(
+        &source,
+        ".data\nmessage: db \"hi\"\nbuffer: resw 8\n.text\n    mov r0 [:buffer + 7]",
+    )
+    .unwrap();
+
+    let mut args = Arguments::from_vec(vec![OsString::from(&source), OsString::from(&binary)]);
+    compile(&mut args).unwrap();
+
+    let file = FileStructure::from_path(&binary).unwrap();
+    assert_eq!(
+        "; heap 0: message, 2 words, line 1\n; heap 1: buffer, 8 words, line 2\n",
+        format_layout(&file)
+    );
+    let mut args = Arguments::from_vec(vec!["--open".into(), OsString::from(&source)]);
+    assert!(run(&mut args, Vec::new()).is_ok());
+}
+
+#[test]
+fn compile_with_includes() {
+    use shitty_types::{Argument, Command};
+
+    let dir = tempfile::tempdir().unwrap();
+    let lib = dir.path().join("lib");
+    std::fs::create_dir(&lib).unwrap();
+    std::fs::write(lib.join("values.s"), ".include \"more.s\"\nONE = 1").unwrap();
+    std::fs::write(lib.join("more.s"), "TWO = 2").unwrap();
+    let source = dir.path().join("main.s");
+    let binary = dir.path().join("main.bin");
+    std::fs::write(&source, ".include \"values.s\"\nmov r0 #(ONE + TWO)").unwrap();
+
+    let mut args = Arguments::from_vec(vec![OsString::from(&source), OsString::from(&binary)]);
+    let error = compile(&mut args).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("cannot find include file values.s"));
+
+    let mut args = Arguments::from_vec(vec![
+        "-I".into(),
+        OsString::from(&lib),
+        OsString::from(&source),
+        OsString::from(&binary),
+    ]);
+    compile(&mut args).unwrap();
+    let file = FileStructure::from_path(&binary).unwrap();
+    assert_eq!(
+        Some(&(Command::Move, [Argument::Register(0), Argument::Raw(3)])),
+        file.program.values().next()
+    );
+}
+
+#[test]
+fn compile_with_defines() {
+    use shitty_types::{Argument, Command};
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("variants.s");
+    let binary = dir.path().join("variants.bin");
+    std::fs::write(
+        &source,
+        ".ifdef DEBUG
+mov r0 #SIZE
+.else
+mov r0 #0
+.endif",
+    )
+    .unwrap();
+
+    let mut args = Arguments::from_vec(vec![
+        "-D".into(),
+        "DEBUG".into(),
+        "--define".into(),
+        "SIZE=0x20".into(),
+        OsString::from(&source),
+        OsString::from(&binary),
+    ]);
+    compile(&mut args).unwrap();
+    let file = FileStructure::from_path(&binary).unwrap();
+    assert_eq!(
+        Some(&(Command::Move, [Argument::Register(0), Argument::Raw(32)])),
+        file.program.values().next()
+    );
+
+    let mut args = Arguments::from_vec(vec![
+        "-D".into(),
+        "SIZE=".into(),
+        OsString::from(&source),
+        OsString::from(&binary),
+    ]);
+    assert!(compile(&mut args).is_err());
+}
+
+#[test]
+fn run_writes_frames() {
+    let dir = tempfile::tempdir().unwrap();
+    let frames = dir.path().join("frames");
+
+    let program = "mov r1 #4294905856\nst [r1 + 1] #15\nflush\nst [r1] #4";
+    let mut args = Arguments::from_vec(vec![
+        "--frames".into(),
+        OsString::from(&frames),
+        program.into(),
+    ]);
+    run(&mut args, Vec::new()).unwrap();
+
+    let first = std::fs::read(frames.join("frame-0000.ppm")).unwrap();
+    let last = std::fs::read(frames.join("frame-0001.ppm")).unwrap();
+    assert_eq!(&first[13..19], &[0, 0, 0, 255, 255, 255]);
+    assert_eq!(&last[13..19], &[170, 0, 0, 255, 255, 255]);
+    assert!(!frames.join("frame-0002.ppm").exists());
+}
+
+#[test]
+fn run_with_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("disk.img");
+    std::fs::write(&image, [0; 1024]).unwrap();
+
+    // write 42 to the first byte of sector 1
+    let program = "mov r1 #4294914048\nst [r1 + 1] #1\nst [r1 + 8] #42\nst [r1] #2\nld r0 [r1]";
+    let mut args = Arguments::from_vec(vec![
+        "--disk".into(),
+        OsString::from(&image),
+        "--output-as-status-code".into(),
+        program.into(),
+    ]);
+    assert_eq!(ExitCode::from(0), run(&mut args, Vec::new()).unwrap());
+
+    let data = std::fs::read(&image).unwrap();
+    assert_eq!(42, data[512]);
+    assert_eq!(1024, data.len());
+}
+
+#[test]
+fn run_many_programs() {
+    let dir = tempfile::tempdir().unwrap();
+    let producer = dir.path().join("producer.s");
+    let consumer = dir.path().join("consumer.s");
+    std::fs::write(&producer, "push #1\npush #41\nfunc :send").unwrap();
+    std::fs::write(&consumer, "push #1\nfunc :recv\npop r0\nadd r0 #1").unwrap();
+
+    let mut args = Arguments::from_vec(vec![OsString::from(&consumer), OsString::from(&producer)]);
+    assert!(run_many(&mut args).is_ok());
+
+    let failing = dir.path().join("failing.s");
+    std::fs::write(&failing, "exit #CODE").unwrap();
+    let mut args = Arguments::from_vec(vec![
+        OsString::from("-D"),
+        OsString::from("CODE=3"),
+        OsString::from(&producer),
+        OsString::from(&failing),
+    ]);
+    assert_eq!(ExitCode::from(3), run_many(&mut args).unwrap());
+
+    let mut args = Arguments::from_vec(vec![OsString::from(&consumer)]);
+    let error = run_many(&mut args).unwrap_err();
+    assert_eq!(
+        "deadlock: every running program is waiting on a channel: 0",
+        error.to_string()
+    );
+}
+
+#[test]
+fn split_program_arguments() {
+    let args = vec![
+        "exec".into(),
+        "prog.bin".into(),
+        "--".into(),
+        "-o".into(),
+        "b".into(),
+    ];
+    let (cli_args, program_args) = split_program_args(args).unwrap();
+    assert_eq!(
+        cli_args,
+        vec![OsString::from("exec"), OsString::from("prog.bin")]
+    );
+    assert_eq!(program_args, vec!["-o", "b"]);
+
+    let (cli_args, program_args) = split_program_args(vec!["help".into()]).unwrap();
+    assert_eq!(cli_args, vec![OsString::from("help")]);
+    assert!(program_args.is_empty());
 }
@@ -2,22 +2,38 @@ use std::fs::{read, write};
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
-use shitty_types::Program;
+use shitty_types::{data_layout, DataEntry, Program, SymbolTable};
+
+/// Bumped whenever the encoding of a `Program` changes incompatibly.
+pub const VERSION: usize = 2;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct FileStructure {
     pub version: usize,
     pub program: Program,
+    /// Names from the source, only used to disassemble the program.
+    #[serde(default)]
+    pub symbols: SymbolTable,
+    /// Where the data of the program is put on the heap, checked against the program on load
+    /// because the runtime places it the same way.
+    pub layout: Vec<DataEntry>,
 }
 
 impl FileStructure {
     pub fn new(program: Program) -> Self {
         FileStructure {
-            version: 0,
+            version: VERSION,
+            layout: data_layout(&program),
             program,
+            symbols: SymbolTable::default(),
         }
     }
 
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
     pub fn dump(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         let mut buffer = Vec::new();
         ciborium::into_writer(&self, &mut buffer)?;
@@ -31,7 +47,18 @@ impl FileStructure {
     }
 
     pub fn load(data: &[u8]) -> Result<FileStructure, Box<dyn std::error::Error>> {
-        Ok(ciborium::from_reader(data)?)
+        let file: FileStructure = ciborium::from_reader(data)?;
+        if file.version != VERSION {
+            return Err(format!(
+                "unsupported file format version {}, expected {VERSION}",
+                file.version
+            )
+            .into());
+        }
+        if file.layout != data_layout(&file.program) {
+            return Err("the data layout does not match the program".into());
+        }
+        Ok(file)
     }
 
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<FileStructure, Box<dyn std::error::Error>> {
@@ -57,6 +84,80 @@ fn save_and_load() {
     assert_eq!(file, file2);
 }
 
+#[test]
+fn load_rejects_other_versions() {
+    let file = FileStructure {
+        version: 0,
+        program: Default::default(),
+        symbols: Default::default(),
+        layout: Default::default(),
+    };
+    let data = file.dump().unwrap();
+
+    assert!(FileStructure::load(&data).is_err());
+}
+
+#[test]
+fn save_and_load_symbols() {
+    use shitty_types::{Argument, Command};
+
+    let program = maplit::btreemap! {
+        1 => (Command::Move, [Argument::Register(0), Argument::Raw(7)]),
+    };
+    let symbols = SymbolTable {
+        constants: maplit::btreemap! { String::from("SEVEN") => 7 },
+        immediates: maplit::btreemap! { 1 => [None, Some(String::from("SEVEN"))] },
+        ..Default::default()
+    };
+
+    let file = FileStructure::new(program).with_symbols(symbols);
+    let file2 = FileStructure::load(&file.dump().unwrap()).unwrap();
+
+    assert_eq!(file, file2);
+}
+
+#[test]
+fn save_and_load_layout() {
+    use shitty_types::{Argument, Command};
+
+    let program = maplit::btreemap! {
+        1 => (Command::Move, [Argument::Register(0), Argument::HeapDeref(20, 1)]),
+        2 => (Command::LabelledData(20), [Argument::Literal(vec![1, 2, 3]), Argument::None]),
+    };
+
+    let file = FileStructure::new(program);
+    let file2 = FileStructure::load(&file.dump().unwrap()).unwrap();
+
+    assert_eq!(
+        vec![DataEntry {
+            label: 20,
+            line: 2,
+            handle: 0,
+            length: 3
+        }],
+        file2.layout
+    );
+    assert_eq!(file, file2);
+}
+
+#[test]
+fn load_rejects_a_different_layout() {
+    use shitty_types::{Argument, Command};
+
+    let program = maplit::btreemap! {
+        2 => (Command::LabelledData(20), [Argument::Literal(vec![1, 2, 3]), Argument::None]),
+    };
+    let mut file = FileStructure::new(program);
+    file.layout[0].length = 4;
+
+    assert_eq!(
+        "the data layout does not match the program",
+        FileStructure::load(&file.dump().unwrap())
+            .unwrap_err()
+            .to_string()
+    );
+}
+
 #[test]
 fn from_to_path() {
     use shitty_types::{Argument, Command};
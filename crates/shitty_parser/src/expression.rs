@@ -0,0 +1,304 @@
+use winnow::ascii::space0;
+use winnow::combinator::{alt, delimited, empty, opt, preceded, repeat};
+use winnow::prelude::*;
+use winnow::token::{any, take_while};
+
+use shitty_types::{Error, Integer};
+
+use crate::identifier;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    LogicalAnd,
+    LogicalOr,
+}
+
+/// Binary operators from the lowest to the highest precedence, the same order as in C.
+const PRECEDENCE: [&[(&str, Operator)]; 10] = [
+    &[("||", Operator::LogicalOr)],
+    &[("&&", Operator::LogicalAnd)],
+    &[("|", Operator::Or)],
+    &[("^", Operator::Xor)],
+    &[("&", Operator::And)],
+    &[("==", Operator::Equal), ("!=", Operator::NotEqual)],
+    &[
+        ("<", Operator::Less),
+        ("<=", Operator::LessEqual),
+        (">", Operator::Greater),
+        (">=", Operator::GreaterEqual),
+    ],
+    &[("<<", Operator::ShiftLeft), (">>", Operator::ShiftRight)],
+    &[("+", Operator::Add), ("-", Operator::Subtract)],
+    &[
+        ("*", Operator::Multiply),
+        ("/", Operator::Divide),
+        ("%", Operator::Modulo),
+    ],
+];
+
+/// A constant expression, evaluated while assembling.
+///
+/// Names are constants or labels, a label is worth the line it is defined on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(Integer),
+    Name(String),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    LogicalNot(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Arithmetic wraps around like it does in the runtime, comparisons give 1 or 0.
+    pub fn evaluate(
+        &self,
+        resolve: &impl Fn(&str) -> Result<Integer, Error>,
+    ) -> Result<Integer, Error> {
+        let value = match self {
+            Expression::Number(value) => *value,
+            Expression::Name(name) => resolve(name)?,
+            Expression::Negate(inner) => inner.evaluate(resolve)?.wrapping_neg(),
+            Expression::Not(inner) => !inner.evaluate(resolve)?,
+            Expression::LogicalNot(inner) => Integer::from(inner.evaluate(resolve)? == 0),
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(resolve)?;
+                let right = right.evaluate(resolve)?;
+                match operator {
+                    Operator::Add => left.wrapping_add(right),
+                    Operator::Subtract => left.wrapping_sub(right),
+                    Operator::Multiply => left.wrapping_mul(right),
+                    Operator::Divide => left
+                        .checked_div(right)
+                        .ok_or_else(|| String::from("division by zero"))?,
+                    Operator::Modulo => left
+                        .checked_rem(right)
+                        .ok_or_else(|| String::from("division by zero"))?,
+                    Operator::And => left & right,
+                    Operator::Or => left | right,
+                    Operator::Xor => left ^ right,
+                    Operator::ShiftLeft => left
+                        .checked_shl(u32::try_from(right).unwrap_or(u32::MAX))
+                        .ok_or_else(|| format!("shift by {right} is too large"))?,
+                    Operator::ShiftRight => left
+                        .checked_shr(u32::try_from(right).unwrap_or(u32::MAX))
+                        .ok_or_else(|| format!("shift by {right} is too large"))?,
+                    Operator::Equal => Integer::from(left == right),
+                    Operator::NotEqual => Integer::from(left != right),
+                    Operator::Less => Integer::from(left < right),
+                    Operator::LessEqual => Integer::from(left <= right),
+                    Operator::Greater => Integer::from(left > right),
+                    Operator::GreaterEqual => Integer::from(left >= right),
+                    Operator::LogicalAnd => Integer::from(left != 0 && right != 0),
+                    Operator::LogicalOr => Integer::from(left != 0 || right != 0),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+pub fn expression(input: &mut &str) -> PResult<Expression> {
+    delimited(space0, |input: &mut &str| binary(input, 0), space0).parse_next(input)
+}
+
+fn binary(input: &mut &str, level: usize) -> PResult<Expression> {
+    let Some(operators) = PRECEDENCE.get(level) else {
+        return unary(input);
+    };
+
+    let mut left = binary(input, level + 1)?;
+    loop {
+        space0.parse_next(input)?;
+        let Some((token, operator)) =
+            operator(input).filter(|(_, operator)| operators.iter().any(|(_, o)| o == operator))
+        else {
+            return Ok(left);
+        };
+        *input = &input[token.len()..];
+        space0.parse_next(input)?;
+        let right = binary(input, level + 1)?;
+        left = Expression::Binary(operator, Box::new(left), Box::new(right));
+    }
+}
+
+/// The longest operator at the start of `input`, so `<` is not taken from `<<`.
+fn operator(input: &str) -> Option<(&'static str, Operator)> {
+    PRECEDENCE
+        .iter()
+        .flat_map(|operators| operators.iter())
+        .filter(|(token, _)| input.starts_with(token))
+        .max_by_key(|(token, _)| token.len())
+        .copied()
+}
+
+fn unary(input: &mut &str) -> PResult<Expression> {
+    alt((
+        preceded(('-', space0), unary).map(|inner| Expression::Negate(Box::new(inner))),
+        preceded(('~', space0), unary).map(|inner| Expression::Not(Box::new(inner))),
+        preceded(('!', space0), unary).map(|inner| Expression::LogicalNot(Box::new(inner))),
+        primary,
+    ))
+    .parse_next(input)
+}
+
+fn primary(input: &mut &str) -> PResult<Expression> {
+    alt((
+        delimited(('(', space0), expression, ')'),
+        character.map(|c| Expression::Number(c as Integer)),
+        number.map(Expression::Number),
+        name.map(|name| Expression::Name(name.to_string())),
+    ))
+    .parse_next(input)
+}
+
+/// A constant or label, labels can be local like `.loop` or include their global label.
+fn name<'s>(input: &mut &'s str) -> PResult<&'s str> {
+    (
+        opt('.'),
+        identifier,
+        repeat::<_, _, (), _, _>(0.., ('.', identifier)),
+    )
+        .recognize()
+        .parse_next(input)
+}
+
+/// A decimal number, or a hexadecimal, binary or octal number with a `0x`, `0b` or `0o` prefix.
+///
+/// Digits can be separated with underscores, like `1_000_000`.
+pub fn number(input: &mut &str) -> PResult<Integer> {
+    let radix = alt((
+        "0x".value(16),
+        "0b".value(2),
+        "0o".value(8),
+        empty.value(10),
+    ))
+    .parse_next(input)?;
+    take_while(1.., move |c: char| c.is_digit(radix) || c == '_')
+        .verify(|digits: &str| !digits.starts_with('_'))
+        .try_map(move |digits: &str| Integer::from_str_radix(&digits.replace('_', ""), radix))
+        .parse_next(input)
+}
+
+/// A character between single quotes, like `'a'` or `'\n'`.
+pub fn character(input: &mut &str) -> PResult<char> {
+    let escaped = alt((
+        'n'.value('\n'),
+        't'.value('\t'),
+        'r'.value('\r'),
+        '0'.value('\0'),
+        '\\'.value('\\'),
+        '\''.value('\''),
+    ));
+    delimited('\'', alt((preceded('\\', escaped), any)), '\'').parse_next(input)
+}
+
+/// Takes the text of an expression up to the first whitespace outside of parentheses and quotes.
+///
+/// Used to cut an immediate out of a line, since `(SIZE * 2)` or `' '` contain spaces.
+pub fn expression_text<'s>(input: &mut &'s str) -> PResult<&'s str> {
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut escaped = false;
+    let end = input
+        .char_indices()
+        .find(|(_, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '\'' => quoted = !quoted,
+                '(' if !quoted => depth += 1,
+                ')' if !quoted => depth = depth.saturating_sub(1),
+                c if c.is_whitespace() && !quoted && depth == 0 => return true,
+                _ => (),
+            }
+            false
+        })
+        .map_or(input.len(), |(index, _)| index);
+
+    let text = &input[..end];
+    *input = &input[end..];
+    Ok(text)
+}
+
+#[cfg(test)]
+fn evaluate(text: &str) -> Result<Integer, Error> {
+    let symbols = maplit::btreemap! { "SIZE" => 4, "start" => 2, "end" => 7 };
+    expression
+        .parse(text)
+        .map_err(|e| e.to_string())?
+        .evaluate(&|name| {
+            symbols
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("unknown {name}"))
+        })
+}
+
+#[test]
+fn evaluate_expressions() {
+    assert_eq!(Ok(9), evaluate("SIZE * 2 + 1"));
+    assert_eq!(Ok(12), evaluate("SIZE * (2 + 1)"));
+    assert_eq!(Ok(5), evaluate("end - start"));
+    assert_eq!(Ok(97), evaluate("'a'"));
+    assert_eq!(Ok(10), evaluate("'\\n'"));
+    assert_eq!(Ok(6), evaluate("5 ^ 3"));
+    assert_eq!(Ok(0x30), evaluate("1 << SIZE | 32 & 48"));
+    assert_eq!(Ok(1), evaluate("~0 >> 63"));
+    assert_eq!(Ok(Integer::MAX), evaluate("-1"));
+    assert_eq!(Ok(2), evaluate("17 % 5"));
+    assert_eq!(Ok(1), evaluate("SIZE >= 4 && end - start > SIZE"));
+    assert_eq!(Ok(0), evaluate("SIZE < 4 || SIZE != 4"));
+    assert_eq!(Ok(1), evaluate("1 << 2 == SIZE"));
+    assert_eq!(Ok(1), evaluate("!(SIZE == 3)"));
+    assert_eq!(
+        Err(String::from("division by zero")),
+        evaluate("1 / (SIZE - 4)")
+    );
+    assert_eq!(
+        Err(String::from("unknown missing")),
+        evaluate("missing + 1")
+    );
+    assert!(evaluate("(1 + 2").is_err());
+    assert!(evaluate("1 +").is_err());
+}
+
+#[test]
+fn parse_numbers() {
+    assert_eq!(Ok(255), number.parse("0xFF"));
+    assert_eq!(Ok(255), number.parse("0xff"));
+    assert_eq!(Ok(10), number.parse("0b1010"));
+    assert_eq!(Ok(15), number.parse("0o17"));
+    assert_eq!(Ok(1_000_000), number.parse("1_000_000"));
+    assert_eq!(Ok(0xDEAD_BEEF), number.parse("0xDEAD_BEEF"));
+    assert!(number.parse("_1").is_err());
+    assert!(number.parse("0o8").is_err());
+    assert!(number.parse("18446744073709551616").is_err());
+}
+
+#[test]
+fn cut_expression_text() {
+    let mut input = "(SIZE * 2) r1";
+    assert_eq!(Ok("(SIZE * 2)"), expression_text(&mut input));
+    assert_eq!(" r1", input);
+
+    let mut input = "' ' r1";
+    assert_eq!(Ok("' '"), expression_text(&mut input));
+    assert_eq!(" r1", input);
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use winnow::prelude::*;
+
+use shitty_types::{Error, Integer};
+
+use crate::label_line_parser;
+use crate::preprocessor::SourceLine;
+
+/// The labels of a program, local labels get a name that includes their global label.
+///
+/// A label starting with a dot, like `.loop`, belongs to the last global label before
+/// it and is named `global.loop`. A numeric label, like `1`, can be defined many times:
+/// `:1b` refers to the nearest one before the line and `:1f` to the nearest one after
+/// it, as long as there is no global label in between.
+#[derive(Debug, Default)]
+pub struct Labels {
+    /// The line every label is defined on, by its full name.
+    addresses: BTreeMap<String, Integer>,
+    /// The global label every line belongs to.
+    scopes: Vec<Rc<str>>,
+    /// The lines a numeric label is defined on, by global label and number.
+    numeric: BTreeMap<(Rc<str>, String), Vec<usize>>,
+}
+
+impl Labels {
+    /// Finds the label definitions, so labels can be used before they are defined.
+    pub fn scan(lines: &[SourceLine]) -> Self {
+        let mut labels = Labels::default();
+        let mut scope: Rc<str> = Rc::from("");
+        for (index, line) in lines.iter().enumerate() {
+            let label = label_line_parser.parse_peek(line.text.trim()).ok();
+            if let Some((_, name)) = label.filter(|(_, name)| is_global(name)) {
+                scope = Rc::from(name);
+            }
+            labels.scopes.push(scope.clone());
+
+            if let Some((_, name)) = label {
+                if is_numeric(name) {
+                    labels
+                        .numeric
+                        .entry((scope.clone(), name.to_string()))
+                        .or_default()
+                        .push(index);
+                }
+                labels
+                    .addresses
+                    .insert(labels.definition(name, index), index as Integer);
+            }
+        }
+        labels
+    }
+
+    /// The full name of the label defined on line `index`.
+    pub fn definition(&self, name: &str, index: usize) -> String {
+        let scope = &self.scopes[index];
+        if name.starts_with('.') {
+            format!("{scope}{name}")
+        } else if is_numeric(name) {
+            format!("{scope}.{name}@{index}")
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// The full name of the label `name` refers to on line `index`.
+    pub fn reference(&self, name: &str, index: usize) -> Result<String, Error> {
+        let scope = &self.scopes[index];
+        if name.starts_with('.') {
+            return Ok(format!("{scope}{name}"));
+        }
+
+        let (number, backwards) = match (name.strip_suffix('b'), name.strip_suffix('f')) {
+            (Some(number), _) if is_numeric(number) => (number, true),
+            (_, Some(number)) if is_numeric(number) => (number, false),
+            _ => return Ok(name.to_string()),
+        };
+        let lines = self
+            .numeric
+            .get(&(scope.clone(), number.to_string()))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let line = if backwards {
+            lines.iter().rev().find(|line| **line < index)
+        } else {
+            lines.iter().find(|line| **line > index)
+        };
+        match line {
+            Some(line) => Ok(self.definition(number, *line)),
+            None if backwards => Err(format!("no label {number} before {name}")),
+            None => Err(format!("no label {number} after {name}")),
+        }
+    }
+
+    /// The line a label is defined on, `name` is resolved like a reference on line `index`.
+    pub fn address(&self, name: &str, index: usize) -> Option<Integer> {
+        let name = self.reference(name, index).ok()?;
+        self.addresses.get(&name).copied()
+    }
+}
+
+fn is_numeric(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_global(name: &str) -> bool {
+    !name.starts_with('.') && !is_numeric(name)
+}
+
+#[test]
+fn resolve_local_labels() {
+    let lines = SourceLine::read(
+        "print:\n.loop:\n1:\nb :1b\nb :1f\n1:\nb :.loop\nother:\n.loop:\nb :1b".as_bytes(),
+        None,
+    )
+    .unwrap();
+    let labels = Labels::scan(&lines);
+
+    assert_eq!("print.loop", labels.definition(".loop", 1));
+    assert_eq!(Ok(String::from("print.1@2")), labels.reference("1b", 3));
+    assert_eq!(Ok(String::from("print.1@5")), labels.reference("1f", 4));
+    assert_eq!(Ok(String::from("print.loop")), labels.reference(".loop", 6));
+    assert_eq!(Ok(String::from("other.loop")), labels.reference(".loop", 9));
+    assert_eq!(Ok(String::from("print")), labels.reference("print", 9));
+    assert_eq!(Some(8), labels.address(".loop", 9));
+    assert_eq!(Some(1), labels.address("print.loop", 9));
+    assert_eq!(
+        Err(String::from("no label 1 before 1b")),
+        labels.reference("1b", 9)
+    );
+}
@@ -1,13 +1,27 @@
+use std::collections::BTreeMap;
 use std::io::{BufRead, BufReader, Cursor};
+use std::path::{Path, PathBuf};
 
-use winnow::ascii::{alpha1, dec_uint, space0};
-use winnow::combinator::{alt, fail, preceded, terminated};
+use winnow::ascii::{alpha1, dec_uint, space0, space1};
+use winnow::combinator::{
+    alt, delimited, eof, fail, opt, preceded, repeat, rest, separated, terminated,
+};
 use winnow::error::{ContextError, ErrMode, ErrorKind, FromExternalError, StrContext};
 use winnow::prelude::*;
 use winnow::stream::AsChar;
-use winnow::token::{take_till, take_while};
+use winnow::token::{any, none_of, one_of, take_till, take_while};
 
-use shitty_types::{hash_label, Argument, Command, Error, Integer, Literal, Program};
+mod expression;
+mod labels;
+mod preprocessor;
+
+use expression::{expression, expression_text, number, Expression};
+use labels::Labels;
+use preprocessor::{Location, Preprocessor, SourceLine};
+use shitty_types::{
+    hash_label, Argument, Command, Error, Integer, Literal, Offset, Program, SymbolTable,
+    MAX_HEAP_ENTRY_SIZE,
+};
 
 pub fn parse_from_str(input: &str) -> Result<Program, Error> {
     let cursor = Cursor::new(input);
@@ -15,57 +29,242 @@ pub fn parse_from_str(input: &str) -> Result<Program, Error> {
 }
 
 pub fn parse(input: impl BufRead) -> Result<Program, Error> {
+    assemble(input).map(|(program, _)| program)
+}
+
+pub fn assemble_from_str(input: &str) -> Result<(Program, SymbolTable), Error> {
+    let cursor = Cursor::new(input);
+    assemble(BufReader::new(cursor))
+}
+
+/// Parses a program and keeps the names of its labels and constants for disassembly.
+pub fn assemble(input: impl BufRead) -> Result<(Program, SymbolTable), Error> {
+    assemble_with(input, &Options::default())
+}
+
+/// Like `assemble`, `.include` paths are relative to the current directory.
+pub fn assemble_with(
+    input: impl BufRead,
+    options: &Options,
+) -> Result<(Program, SymbolTable), Error> {
+    assemble_lines(SourceLine::read(input, None)?, options)
+}
+
+/// Assembles the file at `path`, `.include` paths are relative to the including file.
+pub fn assemble_file<P: AsRef<Path>>(
+    path: P,
+    options: &Options,
+) -> Result<(Program, SymbolTable), Error> {
+    assemble_lines(SourceLine::read_file(path.as_ref())?, options)
+}
+
+/// Settings for assembling a program.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    include_paths: Vec<PathBuf>,
+    defines: BTreeMap<String, Integer>,
+}
+
+impl Options {
+    /// Searches `path` for `.include` files that are not next to the including file.
+    pub fn with_include_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.include_paths.push(path.into());
+        self
+    }
+
+    /// Defines the constant `name` before the first line, like `.equ name value` would.
+    pub fn with_define<S: Into<String>>(mut self, name: S, value: Integer) -> Self {
+        self.defines.insert(name.into(), value);
+        self
+    }
+}
+
+/// Parses `NAME=value` or `NAME`, which defines `NAME` as 1.
+pub fn parse_define(text: &str) -> Result<(String, Integer), Error> {
+    (identifier, opt(preceded('=', number)))
+        .parse(text)
+        .map(|(name, value)| (name.to_string(), value.unwrap_or(1)))
+        .map_err(|_| format!("invalid define `{text}`, expected NAME=value"))
+}
+
+fn assemble_lines(
+    lines: Vec<SourceLine>,
+    options: &Options,
+) -> Result<(Program, SymbolTable), Error> {
+    let lines = Preprocessor::new(options).process(lines)?;
+
     let mut program = Program::default();
+    let mut assembler = Assembler {
+        labels: Labels::scan(&lines),
+        ..Default::default()
+    };
+    assembler.symbols.constants = options.defines.clone();
 
-    for (index, line) in input.lines().enumerate() {
-        let line = line.map_err(|e| e.to_string())?;
-        let mut line_str = line.trim();
-        if line_str.is_empty() || line_str.starts_with(';') {
-            continue;
-        }
-
-        let command =
-            if let Some((remainder, label)) = label_line_parser.parse_peek(&mut line_str).ok() {
-                line_str = remainder;
-                Command::LabelledData(hash_label(label))
-            } else {
-                line_str = line_str.trim();
-                parse_command
-                    .parse_next(&mut line_str)
-                    .map_err(|e| e.to_string())?
-            };
+    for (index, line) in lines.iter().enumerate() {
+        assembler
+            .line(&mut program, index, line)
+            .map_err(|e| format!("{}: {e}", line.location))?;
+    }
+
+    Ok((program, assembler.symbols))
+}
+
+/// The section lines are assembled into, switched with `.data` and `.text`.
+///
+/// The `.data` section only holds labelled data, the `.text` section holds
+/// instructions and can hold data as well.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Section {
+    #[default]
+    Text,
+    Data,
+}
+
+/// What the assembler remembers between lines.
+#[derive(Debug, Default)]
+struct Assembler {
+    symbols: SymbolTable,
+    section: Section,
+    /// Where every constant was defined.
+    definitions: BTreeMap<String, Location>,
+    labels: Labels,
+}
+
+impl Assembler {
+    /// Assembles the line at `index` of the preprocessed program.
+    fn line(
+        &mut self,
+        program: &mut Program,
+        index: usize,
+        line: &SourceLine,
+    ) -> Result<(), Error> {
+        let mut line_str = strip_comment(line.text.trim());
+        if line_str.is_empty() {
+            return Ok(());
+        }
+
+        if let Ok((name, value)) = constant_definition.parse(line_str) {
+            let value = self.evaluate(value, index)?;
+            return self.define(name, value, &line.location);
+        }
+        match line_str {
+            ".text" => {
+                self.section = Section::Text;
+                return Ok(());
+            }
+            ".data" => {
+                self.section = Section::Data;
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        let command = if let Ok((remainder, label)) = label_line_parser.parse_peek(line_str) {
+            line_str = remainder;
+            let name = self.labels.definition(label, index);
+            Command::LabelledData(self.label(&name))
+        } else if self.section == Section::Data {
+            return Err(String::from(
+                "only labelled data can be in the .data section, use .text for instructions",
+            ));
+        } else {
+            line_str = line_str.trim();
+            parse_command
+                .parse_next(&mut line_str)
+                .map_err(|e| e.to_string())?
+        };
         line_str = line_str.trim();
         let mut args = [Argument::None, Argument::None];
 
         if line_str.is_empty() {
             match command {
+                Command::LabelledData(label) if self.section == Section::Data => {
+                    return Err(format!(
+                        "label {} in the .data section has no data",
+                        self.symbols.label_name(label)
+                    ));
+                }
                 Command::LabelledData(label) => {
                     args[0] = Argument::RawLabel(label);
                     program.insert(index as Integer, (Command::Label, args));
-                    continue;
+                    return Ok(());
                 }
-                Command::Return => {
-                    program.insert(index as Integer, (Command::Return, args));
-                    continue;
+                Command::Return
+                | Command::Halt
+                | Command::InterruptReturn
+                | Command::EnableInterrupts
+                | Command::DisableInterrupts
+                | Command::Flush => {
+                    program.insert(index as Integer, (command, args));
+                    return Ok(());
                 }
                 _ => return Err(format!("missing arguments for command: {command:?}")),
             };
         }
 
-        let arg0 = parse_argument(&mut line_str, index).map_err(|e| e.to_string())?;
+        if let Command::LabelledData(_) = command {
+            let data = data_directive(&mut line_str, index, self).map_err(|e| e.to_string())?;
+            args[0] = Argument::Literal(data);
+            program.insert(index as Integer, (command, args));
+            return Ok(());
+        }
+
+        let arg0 = parse_argument(&mut line_str, index, 0, self).map_err(|e| e.to_string())?;
         args[0] = arg0;
 
         line_str = line_str.trim();
         if line_str.is_empty() {
             program.insert(index as Integer, (command, args));
         } else {
-            let arg1 = parse_argument(&mut line_str, index).map_err(|e| e.to_string())?;
+            let arg1 = parse_argument(&mut line_str, index, 1, self).map_err(|e| e.to_string())?;
             args[1] = arg1;
             program.insert(index as Integer, (command, args));
         }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: Integer, location: &Location) -> Result<(), Error> {
+        if let Some(first) = self.definitions.get(name) {
+            return Err(format!(
+                "constant {name} redefined, first defined on {first}"
+            ));
+        }
+        if self.symbols.constants.contains_key(name) {
+            return Err(format!(
+                "constant {name} redefined, first defined in the assembler options"
+            ));
+        }
+        self.definitions.insert(name.to_string(), location.clone());
+        self.symbols.constants.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// A constant, or else the line of a label, for an expression on line `index`.
+    fn symbol(&self, name: &str, index: usize) -> Result<Integer, Error> {
+        self.symbols
+            .constants
+            .get(name)
+            .copied()
+            .or_else(|| self.labels.address(name, index))
+            .ok_or_else(|| format!("unknown constant or label {name}"))
+    }
+
+    fn evaluate(&self, text: &str, index: usize) -> Result<Integer, Error> {
+        let expression = expression
+            .parse(text)
+            .map_err(|_| format!("invalid expression `{}`", text.trim()))?;
+        self.value(&expression, index)
     }
 
-    Ok(program)
+    fn value(&self, expression: &Expression, index: usize) -> Result<Integer, Error> {
+        expression.evaluate(&|name| self.symbol(name, index))
+    }
+
+    fn label(&mut self, name: &str) -> Integer {
+        let label = hash_label(name);
+        self.symbols.labels.insert(label, name.to_string());
+        label
+    }
 }
 
 fn generic_error(input: &mut &str, label: &'static str) -> PResult<()> {
@@ -89,11 +288,47 @@ fn generic_error_with_error<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
     )))
 }
 
-fn label_line_parser<'s>(input: &mut &'s str) -> PResult<&'s str> {
+pub(crate) fn identifier<'s>(input: &mut &'s str) -> PResult<&'s str> {
+    (
+        one_of(|c: char| c.is_alpha() || c == '_'),
+        take_while(0.., |c: char| c.is_alphanum() || c == '_'),
+    )
+        .recognize()
+        .parse_next(input)
+}
+
+/// `.equ NAME value` or `NAME = value`.
+pub(crate) fn constant_definition<'s>(input: &mut &'s str) -> PResult<(&'s str, &'s str)> {
+    alt((
+        preceded((".equ", space1), (identifier, preceded(space1, rest))),
+        (identifier, preceded((space0, '=', space0), rest)),
+    ))
+    .parse_next(input)
+}
+
+/// `text` without its `;` comment, semicolons inside quotes are kept.
+pub(crate) fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', Some(_)) => escaped = true,
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => (),
+            ('"' | '\'', None) => quote = Some(c),
+            (';', None) => return text[..index].trim_end(),
+            _ => (),
+        }
+    }
+    text
+}
+
+pub(crate) fn label_line_parser<'s>(input: &mut &'s str) -> PResult<&'s str> {
     terminated(take_till(1.., |c: char| [':', ' '].contains(&c)), ":").parse_next(input)
 }
 
-fn parse_command<'s>(input: &mut &'s str) -> PResult<Command> {
+fn parse_command(input: &mut &str) -> PResult<Command> {
     let command = match alpha1
         .context(StrContext::Label("parse command"))
         .parse_next(input)?
@@ -117,14 +352,39 @@ fn parse_command<'s>(input: &mut &'s str) -> PResult<Command> {
         "push" => Command::Push,
         "pop" => Command::Pop,
         "ret" => Command::Return,
+        "ld" => Command::Load,
+        "st" => Command::Store,
+        "alloc" => Command::Alloc,
+        "free" => Command::Free,
+        "realloc" => Command::Realloc,
+        "len" => Command::Length,
+        "hlt" => Command::Halt,
+        "exit" => Command::Exit,
+        "int" => Command::Interrupt,
+        "iret" => Command::InterruptReturn,
+        "ivt" => Command::SetInterruptVector,
+        "ei" => Command::EnableInterrupts,
+        "di" => Command::DisableInterrupts,
+        "flush" => Command::Flush,
+        "spawn" => Command::Spawn,
+        "join" => Command::Join,
+        "cas" => Command::CompareAndSwap,
+        "xadd" => Command::FetchAdd,
         _ => return Err(generic_error(input, "invalid command").unwrap_err()),
     };
     Ok(command)
 }
 
-fn parse_argument<'s>(input: &mut &'s str, line: usize) -> PResult<Argument> {
+/// Parses argument number `position` of the instruction on `line`.
+fn parse_argument(
+    input: &mut &str,
+    line: usize,
+    position: usize,
+    assembler: &mut Assembler,
+) -> PResult<Argument> {
     let argument = match alt((
-        ('[', take_while(1.., |c| c != ']') , ']').recognize(),
+        ('[', take_while(1.., |c| c != ']'), ']').recognize(),
+        ('#', expression_text).recognize(),
         take_while(1.., |c| !AsChar::is_space(c)),
     ))
     .context(StrContext::Label("parse argument"))
@@ -146,47 +406,174 @@ fn parse_argument<'s>(input: &mut &'s str, line: usize) -> PResult<Argument> {
         "r13" => Argument::Register(13),
         "r14" => Argument::Register(14),
         "r15" => Argument::Register(15),
-        "db" => {
-            *input = input.trim();
-            let arg = Argument::Literal(parse_db_literal(input)?);
-            *input = "";
-            arg
-        }
-        mut x if x.starts_with("#") => {
-            preceded("#", dec_uint).map(|int| Argument::Raw(int)).parse_next(&mut x)?
+        "cyc" => Argument::CycleCounter,
+        directive @ ("db" | "dw" | "dq" | "resb" | "resw") => {
+            return Err(generic_error_with_error(
+                input,
+                format!("{directive} can only follow a data label, like `name: {directive} ...`"),
+            )
+            .unwrap_err());
         }
+        x if x.starts_with('#') => immediate(&x[1..], line, position, assembler)?,
         mut x if x.contains(':') => {
-            alt((
-                winnow::seq!(_: (space0::<&str, ContextError>, '[', space0, ':'), take_while(1.., |c| !AsChar::is_space(c) && c != '+'), _: (space0, '+', space0), dec_uint, _: (space0, ']', space0)).map(|(label, offset): (&str, usize)| Argument::HeapDeref(hash_label(label), offset)),
-                winnow::seq!(_: (space0::<&str, ContextError>, '[', space0, ':'), take_while(1.., |c| !AsChar::is_space(c) && c != ']'), _: (space0, ']', space0)).map(|(label, )| Argument::HeapDeref(hash_label(label), 0)),
-                winnow::seq!(_: ':', take_while(1.., |c| !AsChar::is_space(c))).map(|(label, )| Argument::RawLabel(hash_label(label))),
-                fail::<&str, _, ContextError>.context(StrContext::Label("invalid label argument")).map(|_: ()| Argument::None)
+            let (argument, label) = alt((
+                winnow::seq!(_: (space0::<&str, ContextError>, '[', space0, ':'), take_while(1.., |c| !AsChar::is_space(c) && c != '+'), _: (space0, '+', space0), register, _: (space0, ']', space0)).map(|(label, reg): (&str, u8)| (Argument::HeapIndex(hash_label(label), reg), label)),
+                winnow::seq!(_: (space0::<&str, ContextError>, '[', space0, ':'), take_while(1.., |c| !AsChar::is_space(c) && c != '+'), _: (space0, '+', space0), number.try_map(usize::try_from), _: (space0, ']', space0)).map(|(label, offset): (&str, usize)| (Argument::HeapDeref(hash_label(label), offset), label)),
+                winnow::seq!(_: (space0::<&str, ContextError>, '[', space0, ':'), take_while(1.., |c| !AsChar::is_space(c) && c != ']'), _: (space0, ']', space0)).map(|(label, )| (Argument::HeapDeref(hash_label(label), 0), label)),
+                winnow::seq!(_: ':', take_while(1.., |c| !AsChar::is_space(c))).map(|(label, )| (Argument::RawLabel(hash_label(label)), label)),
+                fail::<&str, _, ContextError>.context(StrContext::Label("invalid label argument")).map(|_: ()| (Argument::None, ""))
             )
-            ).parse_next(&mut x)?
+            ).parse_next(&mut x)?;
+            let name = assembler
+                .labels
+                .reference(label, line)
+                .map_err(|e| generic_error_with_error(input, e).unwrap_err())?;
+            let label = assembler.label(&name);
+            match argument {
+                Argument::HeapIndex(_, register) => Argument::HeapIndex(label, register),
+                Argument::HeapDeref(_, offset) => Argument::HeapDeref(label, offset),
+                Argument::RawLabel(_) => Argument::RawLabel(label),
+                other => other,
+            }
         }
+        mut x if x.starts_with('[') => memory_operand.parse_next(&mut x)?,
         other => {
-            return Err(generic_error_with_error(input, format!("invalid argument: got : `{}` on line: {}", other, line)).unwrap_err());
+            return Err(generic_error_with_error(
+                input,
+                format!("invalid argument: got : `{}`", other),
+            )
+            .unwrap_err());
         }
     };
     Ok(argument)
 }
 
-fn parse_db_literal(input: &mut &str) -> PResult<Literal> {
-    let mut output = Vec::new();
-    for item in input.split(',') {
-        let data: tinyjson::JsonValue = item
-            .trim()
+/// The part of an immediate after the `#`, a decimal number or a constant expression.
+fn immediate(
+    mut input: &str,
+    line: usize,
+    position: usize,
+    assembler: &mut Assembler,
+) -> PResult<Argument> {
+    let value = assembler
+        .evaluate(input, line)
+        .map_err(|e| generic_error_with_error(&mut input, e).unwrap_err())?;
+    if dec_uint::<_, Integer, ContextError>.parse(input).is_err() {
+        assembler
+            .symbols
+            .immediates
+            .entry(line as Integer)
+            .or_default()[position] = Some(input.to_string());
+    }
+    Ok(Argument::Raw(value))
+}
+
+fn register(input: &mut &str) -> PResult<u8> {
+    preceded('r', dec_uint)
+        .verify(|reg: &u8| *reg < 16)
+        .parse_next(input)
+}
+
+fn offset(input: &mut &str) -> PResult<Offset> {
+    alt((register.map(Offset::Register), number.map(Offset::Raw))).parse_next(input)
+}
+
+fn memory_operand(input: &mut &str) -> PResult<Argument> {
+    winnow::seq!(
+        _: ('[', space0),
+        register,
+        opt(preceded((space0, '+', space0), offset)),
+        _: (space0, ']'),
+    )
+    .map(|(base, offset)| Argument::Memory(base, offset.unwrap_or(Offset::Raw(0))))
+    .context(StrContext::Label("invalid memory argument"))
+    .parse_next(input)
+}
+
+/// The data after a data label, the items of `db`, `dw` and `dq` or the zeroed
+/// items reserved by `resb` and `resw`.
+///
+/// Every item takes a word on the heap, whatever its size, so `resb` and `resw`
+/// reserve the same and `dw` only checks that its items fit in 16 bits.
+fn data_directive(input: &mut &str, line: usize, assembler: &Assembler) -> PResult<Literal> {
+    let directive = terminated(take_while(1.., |c| !AsChar::is_space(c)), space0)
+        .context(StrContext::Label("data directive"))
+        .parse_next(input)?;
+    match directive {
+        "db" | "dq" => parse_db_literal(input, line, assembler),
+        "dw" => {
+            let data = parse_db_literal(input, line, assembler)?;
+            if let Some(value) = data.iter().find(|value| **value > u16::MAX as Integer) {
+                return Err(generic_error_with_error(
+                    input,
+                    format!("dw {value} does not fit in 16 bits"),
+                )
+                .unwrap_err());
+            }
+            Ok(data)
+        }
+        "resb" | "resw" => {
+            let count = assembler
+                .evaluate(input, line)
+                .and_then(|count| match usize::try_from(count) {
+                    Ok(count) if count <= MAX_HEAP_ENTRY_SIZE => Ok(count),
+                    _ => Err(format!(
+                        "{directive} {count} is larger than the maximum of {MAX_HEAP_ENTRY_SIZE} words"
+                    )),
+                })
+                .map_err(|e| generic_error_with_error(input, e).unwrap_err())?;
+            Ok(vec![0; count])
+        }
+        other => Err(generic_error_with_error(
+            input,
+            format!("expected db, dw, dq, resb or resw after a data label, got `{other}`"),
+        )
+        .unwrap_err()),
+    }
+}
+
+/// A comma separated list of strings and constant expressions, like `"Hi", 0x0A, 0`.
+fn parse_db_literal(input: &mut &str, line: usize, assembler: &Assembler) -> PResult<Literal> {
+    let items: Vec<Literal> = terminated(
+        separated(1.., |input: &mut &str| db_item(input, line, assembler), ','),
+        eof,
+    )
+    .parse_next(input)?;
+    Ok(items.concat())
+}
+
+fn db_item(input: &mut &str, line: usize, assembler: &Assembler) -> PResult<Literal> {
+    if let Ok(text) = delimited(space0, string_literal, space0).parse_next(input) {
+        let data: tinyjson::JsonValue = text
             .parse()
-            .map_err(|e: tinyjson::JsonParseError| e.to_string())
             .map_err(|_| generic_error(input, "invalid db literal").unwrap_err())?;
-        match data {
-            tinyjson::JsonValue::String(x) => output.extend(x.chars().map(|x| x as Integer)),
-            tinyjson::JsonValue::Number(x) => output.push(x as Integer),
-            _ => return Err(generic_error(input, "invalid db literal").unwrap_err()),
-        }
+        return match data {
+            tinyjson::JsonValue::String(x) => Ok(x.chars().map(|x| x as Integer).collect()),
+            _ => Err(generic_error(input, "invalid db literal").unwrap_err()),
+        };
     }
 
-    Ok(output)
+    let expression = expression
+        .context(StrContext::Label("invalid db literal"))
+        .parse_next(input)?;
+    let value = assembler
+        .value(&expression, line)
+        .map_err(|e| generic_error_with_error(input, e).unwrap_err())?;
+    Ok(vec![value])
+}
+
+/// A string between double quotes, with the escapes of a JSON string.
+fn string_literal<'s>(input: &mut &'s str) -> PResult<&'s str> {
+    (
+        '"',
+        repeat::<_, _, (), _, _>(
+            0..,
+            alt((preceded('\\', any).void(), none_of(['"', '\\']).void())),
+        ),
+        '"',
+    )
+        .recognize()
+        .parse_next(input)
 }
 
 #[test]
@@ -322,3 +709,565 @@ data_str: db ""
         }
     );
 }
+
+#[test]
+fn parse_cycle_counter() {
+    let input = r#"
+    mov r0 cyc
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Move, [Argument::Register(0), Argument::CycleCounter]),
+        }
+    );
+}
+
+#[test]
+fn parse_program_with_memory() {
+    let input = r#"
+    st [r1] r0
+    st [r1 + 8] #5
+    ld r2 [ r1 + r3 ]
+    ld r4 [r15+16]
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Store, [Argument::Memory(1, Offset::Raw(0)), Argument::Register(0)]),
+            2 => (Command::Store, [Argument::Memory(1, Offset::Raw(8)), Argument::Raw(5)]),
+            3 => (Command::Load, [Argument::Register(2), Argument::Memory(1, Offset::Register(3))]),
+            4 => (Command::Load, [Argument::Register(4), Argument::Memory(15, Offset::Raw(16))]),
+        }
+    );
+}
+
+#[test]
+fn parse_program_with_heap_index() {
+    let input = r#"
+data_str: db "Hallo"
+    mov r0 [:data_str + r1]
+    mov [ :data_str+r15 ] r2
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+    let data_str = 12529907765057034586;
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::LabelledData(data_str), [Argument::Literal("Hallo".chars().map(|x| x as Integer).collect()), Argument::None]),
+            2 => (Command::Move, [Argument::Register(0), Argument::HeapIndex(data_str, 1)]),
+            3 => (Command::Move, [Argument::HeapIndex(data_str, 15), Argument::Register(2)]),
+        }
+    );
+}
+
+#[test]
+fn parse_program_with_allocations() {
+    let input = r#"
+    alloc :buffer #16
+    realloc :buffer r1
+    len r0 :buffer
+    free :buffer
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+    let buffer = hash_label("buffer");
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(16)]),
+            2 => (Command::Realloc, [Argument::RawLabel(buffer), Argument::Register(1)]),
+            3 => (Command::Length, [Argument::Register(0), Argument::RawLabel(buffer)]),
+            4 => (Command::Free, [Argument::RawLabel(buffer), Argument::None]),
+        }
+    );
+}
+
+#[test]
+fn parse_program_with_halt_and_exit() {
+    let input = r#"
+    exit #3
+    exit r1
+    hlt
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Exit, [Argument::Raw(3), Argument::None]),
+            2 => (Command::Exit, [Argument::Register(1), Argument::None]),
+            3 => (Command::Halt, [Argument::None, Argument::None]),
+        }
+    );
+}
+
+#[test]
+fn parse_program_with_interrupts() {
+    let input = r#"
+    ivt #0 :on_divide
+    int #32
+on_divide:
+    iret
+    ei
+    di
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+    let on_divide = hash_label("on_divide");
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::SetInterruptVector, [Argument::Raw(0), Argument::RawLabel(on_divide)]),
+            2 => (Command::Interrupt, [Argument::Raw(32), Argument::None]),
+            3 => (Command::Label, [Argument::RawLabel(on_divide), Argument::None]),
+            4 => (Command::InterruptReturn, [Argument::None, Argument::None]),
+            5 => (Command::EnableInterrupts, [Argument::None, Argument::None]),
+            6 => (Command::DisableInterrupts, [Argument::None, Argument::None]),
+        }
+    );
+}
+
+#[test]
+fn parse_flush() {
+    let program = parse_from_str("st [r1 + 2] #12\nflush").unwrap();
+
+    assert_eq!(
+        program[&1],
+        (Command::Flush, [Argument::None, Argument::None])
+    );
+}
+
+#[test]
+fn parse_core_instructions() {
+    let input = r#"
+    spawn r1 :worker
+    join r1
+worker:
+    cas [r2] r3
+    xadd [r2 + 1] r3
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+    let worker = hash_label("worker");
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Spawn, [Argument::Register(1), Argument::RawLabel(worker)]),
+            2 => (Command::Join, [Argument::Register(1), Argument::None]),
+            3 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+            4 => (Command::CompareAndSwap, [Argument::Memory(2, Offset::Raw(0)), Argument::Register(3)]),
+            5 => (Command::FetchAdd, [Argument::Memory(2, Offset::Raw(1)), Argument::Register(3)]),
+        }
+    );
+}
+
+#[test]
+fn parse_program_with_constants() {
+    let input = r#"
+.equ SIZE 9
+LAST = SIZE ; the last one
+    mov r5 #SIZE
+    cmp r5 #LAST
+    "#;
+
+    let (program, symbols) = assemble_from_str(input).unwrap();
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            3 => (Command::Move, [Argument::Register(5), Argument::Raw(9)]),
+            4 => (Command::Compare, [Argument::Register(5), Argument::Raw(9)]),
+        }
+    );
+    assert_eq!(Some(&9), symbols.constants.get("LAST"));
+    assert_eq!(
+        Some(&[None, Some(String::from("LAST"))]),
+        symbols.immediates.get(&4)
+    );
+}
+
+#[test]
+fn constant_errors() {
+    let error = parse_from_str("SIZE = 1\n.equ SIZE 2").unwrap_err();
+    assert_eq!(
+        "line 2: constant SIZE redefined, first defined on line 1",
+        error
+    );
+
+    let error = parse_from_str("SIZE = 1 ; one\n.equ SIZE 2 ; two").unwrap_err();
+    assert_eq!(
+        "line 2: constant SIZE redefined, first defined on line 1",
+        error
+    );
+
+    let error = parse_from_str("mov r0 #SIZE").unwrap_err();
+    assert!(
+        error.contains("line 1: ") && error.contains("unknown constant or label SIZE"),
+        "{error}"
+    );
+}
+
+#[test]
+fn assembled_program_formats_with_names() {
+    let input = "COUNT = 3\nloop:\n    sub r0 #COUNT\n    b :loop";
+
+    let (program, symbols) = assemble_from_str(input).unwrap();
+
+    assert_eq!(
+        ".equ COUNT 3\nloop:\n    sub r0 #COUNT\n    b :loop\n",
+        shitty_types::format_program_with_symbols(&program, &symbols, Default::default())
+    );
+}
+
+#[test]
+fn parse_program_with_expressions() {
+    let input = r#"
+SIZE = 4
+.equ TOTAL SIZE * 2 + 1
+start:
+    mov r0 #(SIZE * 2 + 1)
+    mov r1 #' '
+    mov r2 #(end - start)
+    mov r3 #TOTAL
+end:
+    "#;
+
+    let (program, symbols) = assemble_from_str(input).unwrap();
+
+    assert_eq!(Some(&9), symbols.constants.get("TOTAL"));
+    assert_eq!(
+        program[&4],
+        (Command::Move, [Argument::Register(0), Argument::Raw(9)])
+    );
+    assert_eq!(
+        program[&5],
+        (Command::Move, [Argument::Register(1), Argument::Raw(32)])
+    );
+    assert_eq!(
+        program[&6],
+        (Command::Move, [Argument::Register(2), Argument::Raw(5)])
+    );
+    assert_eq!(
+        Some(&[None, Some(String::from("(end - start)"))]),
+        symbols.immediates.get(&6)
+    );
+
+    let error = parse_from_str("mov r0 #(1 / 0)").unwrap_err();
+    assert!(
+        error.starts_with("line 1: ") && error.contains("division by zero"),
+        "{error}"
+    );
+}
+
+#[test]
+fn parse_number_literals() {
+    let input = r#"
+    mov r0 #0xFF
+    mov r1 #0b1010
+    mov r2 #0o17
+    mov r3 #'A'
+    mov r4 #'\n'
+    mov r5 #1_000_000
+    ld r6 [r1 + 0x10]
+data: db "a,b", 0x41, 'B', '\n', 0b11, 1_000
+    "#;
+
+    let program = parse_from_str(input).unwrap();
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Move, [Argument::Register(0), Argument::Raw(255)]),
+            2 => (Command::Move, [Argument::Register(1), Argument::Raw(10)]),
+            3 => (Command::Move, [Argument::Register(2), Argument::Raw(15)]),
+            4 => (Command::Move, [Argument::Register(3), Argument::Raw(65)]),
+            5 => (Command::Move, [Argument::Register(4), Argument::Raw(10)]),
+            6 => (Command::Move, [Argument::Register(5), Argument::Raw(1_000_000)]),
+            7 => (Command::Load, [Argument::Register(6), Argument::Memory(1, Offset::Raw(16))]),
+            8 => (Command::LabelledData(hash_label("data")), [Argument::Literal(vec![97, 44, 98, 65, 66, 10, 3, 1000]), Argument::None]),
+        }
+    );
+
+    assert!(parse_from_str("mov r0 #0x").is_err());
+    assert!(parse_from_str("mov r0 #0b12").is_err());
+    assert!(parse_from_str("mov r0 #0x1_0000_0000_0000_0000").is_err());
+    assert!(parse_from_str("data: db 1,").is_err());
+}
+
+#[test]
+fn parse_program_with_macros() {
+    let input = r#"
+.macro print_value value
+    push \value
+    func :print_number
+.endm
+.macro count_down register
+loop\@:
+    print_value \register
+    sub \register #1
+    cmp \register #0
+    bg :loop\@
+.endm
+    mov r0 #3
+    count_down r0
+    count_down r1
+    "#;
+
+    let (program, symbols) = assemble_from_str(input).unwrap();
+    let first_loop = hash_label("loop1");
+
+    assert_eq!(
+        program.values().take(8).cloned().collect::<Vec<_>>(),
+        vec![
+            (Command::Move, [Argument::Register(0), Argument::Raw(3)]),
+            (
+                Command::Label,
+                [Argument::RawLabel(first_loop), Argument::None]
+            ),
+            (Command::Push, [Argument::Register(0), Argument::None]),
+            (
+                Command::Function,
+                [
+                    Argument::RawLabel(hash_label("print_number")),
+                    Argument::None
+                ]
+            ),
+            (Command::Subtract, [Argument::Register(0), Argument::Raw(1)]),
+            (Command::Compare, [Argument::Register(0), Argument::Raw(0)]),
+            (
+                Command::BranchGreater,
+                [Argument::RawLabel(first_loop), Argument::None]
+            ),
+            (
+                Command::Label,
+                [Argument::RawLabel(hash_label("loop3")), Argument::None]
+            ),
+        ]
+    );
+    assert_eq!("loop1", symbols.label_name(first_loop));
+
+    let error = parse_from_str(".macro bad\n    mov r0 #MISSING\n.endm\n    bad").unwrap_err();
+    assert!(
+        error.starts_with("line 2 in macro bad (defined on line 1) expanded on line 4: "),
+        "{error}"
+    );
+}
+
+#[test]
+fn parse_program_with_local_labels() {
+    let input = r#"
+print:
+.loop:
+    sub r0 #1
+    bne :.loop
+1:
+    b :1f
+    b :1b
+1:
+    mov r1 #(.end - .loop)
+.end:
+    ret
+other:
+.loop:
+    b :.loop
+    "#;
+
+    let (program, symbols) = assemble_from_str(input).unwrap();
+    let print_loop = hash_label("print.loop");
+    let other_loop = hash_label("other.loop");
+
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Label, [Argument::RawLabel(hash_label("print")), Argument::None]),
+            2 => (Command::Label, [Argument::RawLabel(print_loop), Argument::None]),
+            3 => (Command::Subtract, [Argument::Register(0), Argument::Raw(1)]),
+            4 => (Command::BranchNotEqual, [Argument::RawLabel(print_loop), Argument::None]),
+            5 => (Command::Label, [Argument::RawLabel(hash_label("print.1@5")), Argument::None]),
+            6 => (Command::Branch, [Argument::RawLabel(hash_label("print.1@8")), Argument::None]),
+            7 => (Command::Branch, [Argument::RawLabel(hash_label("print.1@5")), Argument::None]),
+            8 => (Command::Label, [Argument::RawLabel(hash_label("print.1@8")), Argument::None]),
+            9 => (Command::Move, [Argument::Register(1), Argument::Raw(8)]),
+            10 => (Command::Label, [Argument::RawLabel(hash_label("print.end")), Argument::None]),
+            11 => (Command::Return, [Argument::None, Argument::None]),
+            12 => (Command::Label, [Argument::RawLabel(hash_label("other")), Argument::None]),
+            13 => (Command::Label, [Argument::RawLabel(other_loop), Argument::None]),
+            14 => (Command::Branch, [Argument::RawLabel(other_loop), Argument::None]),
+        }
+    );
+    assert_eq!("other.loop", symbols.label_name(other_loop));
+
+    let error = parse_from_str("start:\n    b :1b").unwrap_err();
+    assert!(error.starts_with("line 2: "), "{error}");
+    assert!(error.contains("no label 1 before 1b"), "{error}");
+}
+
+#[test]
+fn parse_program_with_conditionals() {
+    let input = r#".ifndef SIZE
+SIZE = 8
+.endif
+.if SIZE > 8 && DEBUG
+    mov r0 #1
+.elif DEBUG
+    mov r0 #SIZE
+.else
+    mov r0 #0
+.endif"#;
+
+    let options = Options::default().with_define("DEBUG", 1);
+    let (program, _) = assemble_with(input.as_bytes(), &options).unwrap();
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            1 => (Command::Move, [Argument::Register(0), Argument::Raw(8)]),
+        }
+    );
+
+    let options = options.with_define("SIZE", 16);
+    let (program, symbols) = assemble_with(input.as_bytes(), &options).unwrap();
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            0 => (Command::Move, [Argument::Register(0), Argument::Raw(1)]),
+        }
+    );
+    assert_eq!(Some(&16), symbols.constants.get("SIZE"));
+
+    let error = assemble_with("DEBUG = 0".as_bytes(), &options).unwrap_err();
+    assert_eq!(
+        "line 1: constant DEBUG redefined, first defined in the assembler options",
+        error
+    );
+
+    assert_eq!(Ok((String::from("SIZE"), 0x10)), parse_define("SIZE=0x10"));
+    assert_eq!(Ok((String::from("DEBUG"), 1)), parse_define("DEBUG"));
+    assert!(parse_define("1=2").is_err());
+}
+
+#[test]
+fn parse_program_with_sections() {
+    let input = r#"SIZE = 2
+.data
+message: db "hi", 0
+table: db 1, 0xFFFF
+buffer: resw SIZE * 2
+.text
+    mov r0 [:table + 1]
+.data
+big: db -1"#;
+
+    let (program, symbols) = assemble_from_str(input).unwrap();
+    assert_eq!(
+        program,
+        maplit::btreemap! {
+            2 => (Command::LabelledData(hash_label("message")), [Argument::Literal(vec![104, 105, 0]), Argument::None]),
+            3 => (Command::LabelledData(hash_label("table")), [Argument::Literal(vec![1, 0xFFFF]), Argument::None]),
+            4 => (Command::LabelledData(hash_label("buffer")), [Argument::Literal(vec![0; 4]), Argument::None]),
+            6 => (Command::Move, [Argument::Register(0), Argument::HeapDeref(hash_label("table"), 1)]),
+            8 => (Command::LabelledData(hash_label("big")), [Argument::Literal(vec![Integer::MAX]), Argument::None]),
+        }
+    );
+    let formatted =
+        shitty_types::format_program_with_symbols(&program, &symbols, Default::default());
+    assert_eq!(
+        r#".equ SIZE 2
+.data
+message: db "hi",0
+table: db 1,65535
+buffer: resw 4
+.text
+    mov r0 [:table + 1]
+.data
+big: db 18446744073709551615
+"#,
+        formatted
+    );
+    assert_eq!(program, assemble_from_str(&formatted).unwrap().0);
+
+    let error = parse_from_str(".data\n    mov r0 #1").unwrap_err();
+    assert_eq!(
+        "line 2: only labelled data can be in the .data section, use .text for instructions",
+        error
+    );
+    let error = parse_from_str(".data\nstart:").unwrap_err();
+    assert_eq!(
+        "line 2: label start in the .data section has no data",
+        error
+    );
+
+    let error = parse_from_str("huge: resw 1 << 40").unwrap_err();
+    assert!(
+        error.contains("is larger than the maximum of 16777216 words"),
+        "{error}"
+    );
+    let error = parse_from_str("word: dd 1").unwrap_err();
+    assert!(
+        error.contains("expected db, dw, dq, resb or resw after a data label, got `dd`"),
+        "{error}"
+    );
+    let error = parse_from_str("half: dw 1, 0x10000").unwrap_err();
+    assert!(
+        error.contains("dw 65536 does not fit in 16 bits"),
+        "{error}"
+    );
+    let error = parse_from_str("    push dq 2").unwrap_err();
+    assert!(error.contains("dq can only follow a data label"), "{error}");
+    let error = parse_from_str("    push resw 2").unwrap_err();
+    assert!(
+        error.contains("resw can only follow a data label"),
+        "{error}"
+    );
+}
+
+#[test]
+fn parse_data_directives() {
+    let input = r#"SIZE = 3
+.data
+chars: db "a", 1
+half: dw 0xFFFF, SIZE
+quad: dq -1, 'b'
+bytes: resb SIZE
+words: resw 2"#;
+
+    let (program, _) = assemble_from_str(input).unwrap();
+    let data: Vec<_> = program
+        .values()
+        .filter_map(|(_, [argument, _])| match argument {
+            Argument::Literal(data) => Some(data.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            vec![97, 1],
+            vec![0xFFFF, 3],
+            vec![Integer::MAX, 98],
+            vec![0; 3],
+            vec![0; 2]
+        ],
+        data
+    );
+
+    let layout: Vec<_> = shitty_types::data_layout(&program)
+        .into_iter()
+        .map(|entry| (entry.line, entry.handle, entry.length))
+        .collect();
+    assert_eq!(
+        vec![(2, 0, 2), (3, 1, 2), (4, 2, 2), (5, 3, 3), (6, 4, 2)],
+        layout
+    );
+}
@@ -0,0 +1,721 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use winnow::ascii::{space0, space1};
+use winnow::combinator::{alt, delimited, eof, opt, preceded, rest};
+use winnow::prelude::*;
+use winnow::token::take_till;
+
+use shitty_types::{Error, Integer};
+
+use crate::expression::expression;
+use crate::{constant_definition, identifier, strip_comment, Options};
+
+/// Macros can call other macros, but not deeper than this.
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// A line of the program after preprocessing, with where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub text: String,
+    pub location: Location,
+}
+
+impl SourceLine {
+    /// Reads all lines of `input`, which is read from `file` if it is a file.
+    pub fn read(input: impl BufRead, file: Option<Rc<Path>>) -> Result<Vec<Self>, Error> {
+        input
+            .lines()
+            .enumerate()
+            .map(|(index, text)| {
+                Ok(SourceLine {
+                    text: text.map_err(|e| e.to_string())?,
+                    location: Location {
+                        file: file.clone(),
+                        line: index + 1,
+                        expansion: None,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    pub fn read_file(path: &Path) -> Result<Vec<Self>, Error> {
+        let file = File::open(path).map_err(|e| format!("opening {}: {e}", path.display()))?;
+        SourceLine::read(BufReader::new(file), Some(Rc::from(path)))
+            .map_err(|e| format!("reading {}: {e}", path.display()))
+    }
+}
+
+/// Where a line comes from, errors start with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// The file the line is in, `None` when the source is not read from a file.
+    pub file: Option<Rc<Path>>,
+    /// The line in the source, starting at 1.
+    pub line: usize,
+    /// The macro call the line was expanded from.
+    pub expansion: Option<Rc<Expansion>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansion {
+    pub name: String,
+    pub definition: Location,
+    pub call: Location,
+}
+
+impl Location {
+    /// The directory `.include` paths in this file are relative to.
+    fn directory(&self) -> PathBuf {
+        self.file
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(file) = &self.file {
+            write!(f, " of {}", file.display())?;
+        }
+        if let Some(expansion) = &self.expansion {
+            write!(
+                f,
+                " in macro {} (defined on {}) expanded on {}",
+                expansion.name, expansion.definition, expansion.call
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A conditional assembly directive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Directive<'s> {
+    If(&'s str),
+    Ifdef(&'s str),
+    Ifndef(&'s str),
+    Elif(&'s str),
+    Else,
+    Endif,
+}
+
+/// An `.if` that is not closed yet.
+#[derive(Debug)]
+struct Condition {
+    location: Location,
+    /// Whether the lines around the `.if` are assembled.
+    enclosing: bool,
+    /// Whether one of the branches so far was taken.
+    taken: bool,
+    /// Whether the lines of the current branch are assembled.
+    active: bool,
+    has_else: bool,
+}
+
+#[derive(Debug)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<SourceLine>,
+    location: Location,
+}
+
+/// Expands macros and includes files before the lines are assembled.
+///
+/// `.include "path"` is replaced with the lines of that file. The path is relative to
+/// the including file, or else to one of the include paths.
+///
+/// A macro is defined with `.macro name a, b` up to `.endm`. In its body `\a` is
+/// replaced with the argument for `a` and `\@` with a number that is unique to each
+/// expansion, so `loop\@:` gives every expansion its own label. It is called like an
+/// instruction, with its arguments separated by commas: `name r0, #(SIZE + 1)`.
+///
+/// Lines between `.if expression`, `.elif expression`, `.else` and `.endif` are only
+/// kept when their condition is not zero, `.ifdef NAME` and `.ifndef NAME` check
+/// whether a constant is defined. Conditions can use the constants defined before
+/// them and in the options, but not labels.
+#[derive(Debug, Default)]
+pub struct Preprocessor {
+    macros: BTreeMap<String, Macro>,
+    /// The constants conditions can use.
+    constants: BTreeMap<String, Integer>,
+    expansions: usize,
+    include_paths: Vec<PathBuf>,
+    /// The files being included, with the outermost first.
+    including: Vec<(PathBuf, Rc<Path>)>,
+    output: Vec<SourceLine>,
+}
+
+impl Preprocessor {
+    pub fn new(options: &Options) -> Self {
+        Preprocessor {
+            include_paths: options.include_paths.clone(),
+            constants: options.defines.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn process(mut self, lines: Vec<SourceLine>) -> Result<Vec<SourceLine>, Error> {
+        let root = lines.first().and_then(|line| line.location.file.clone());
+        if let Some(file) = root {
+            let canonical = file.canonicalize().map_err(|e| e.to_string())?;
+            self.including.push((canonical, file));
+        }
+        self.lines(lines, 0)?;
+        Ok(self.output)
+    }
+
+    fn lines(&mut self, lines: Vec<SourceLine>, depth: usize) -> Result<(), Error> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            let text = line.text.trim();
+            let code = strip_comment(text);
+            if let Ok(directive) = conditional_directive.parse(code) {
+                self.conditional(directive, &line.location, &mut conditions)?;
+                continue;
+            }
+            if !conditions.last().is_none_or(|condition| condition.active) {
+                continue;
+            }
+
+            if let Ok((name, parameters)) = macro_definition.parse(code) {
+                let definition = self.definition(parameters, &line.location, &mut lines)?;
+                if let Some(first) = self.macros.get(name) {
+                    return Err(format!(
+                        "{}: macro {name} redefined, first defined on {}",
+                        line.location, first.location
+                    ));
+                }
+                self.macros.insert(name.to_string(), definition);
+            } else if code == ".endm" {
+                return Err(format!("{}: .endm without .macro", line.location));
+            } else if let Ok(path) = include_directive.parse(code) {
+                self.include(path, &line.location, depth)?;
+            } else if let Some((name, arguments)) = self.call(code) {
+                let expanded = self.expand(name, arguments, &line.location, depth)?;
+                self.lines(expanded, depth + 1)?;
+            } else {
+                if let Ok((name, value)) = constant_definition.parse(code) {
+                    // Constants that use labels are left to the assembler
+                    if let Ok(value) = self.evaluate(value) {
+                        self.constants.insert(name.to_string(), value);
+                    }
+                }
+                self.output.push(line);
+            }
+        }
+        match conditions.last() {
+            Some(condition) => Err(format!("{}: .if without .endif", condition.location)),
+            None => Ok(()),
+        }
+    }
+
+    fn conditional(
+        &self,
+        directive: Directive,
+        location: &Location,
+        conditions: &mut Vec<Condition>,
+    ) -> Result<(), Error> {
+        let enclosing = conditions.last().is_none_or(|condition| condition.active);
+        let test = match directive {
+            Directive::If("") => return Err(format!("{location}: .if needs an expression")),
+            Directive::Elif("") => return Err(format!("{location}: .elif needs an expression")),
+            Directive::If(text) => enclosing && self.condition(text, location)?,
+            Directive::Ifdef(name) => self.constants.contains_key(name),
+            Directive::Ifndef(name) => !self.constants.contains_key(name),
+            Directive::Elif(text) => {
+                let Some(condition) = conditions.last_mut() else {
+                    return Err(format!("{location}: .elif without .if"));
+                };
+                if condition.has_else {
+                    return Err(format!("{location}: .elif after .else"));
+                }
+                let active =
+                    condition.enclosing && !condition.taken && self.condition(text, location)?;
+                condition.taken |= active;
+                condition.active = active;
+                return Ok(());
+            }
+            Directive::Else => {
+                let Some(condition) = conditions.last_mut() else {
+                    return Err(format!("{location}: .else without .if"));
+                };
+                if condition.has_else {
+                    return Err(format!("{location}: .else after .else"));
+                }
+                condition.active = condition.enclosing && !condition.taken;
+                condition.taken = true;
+                condition.has_else = true;
+                return Ok(());
+            }
+            Directive::Endif => {
+                return match conditions.pop() {
+                    Some(_) => Ok(()),
+                    None => Err(format!("{location}: .endif without .if")),
+                };
+            }
+        };
+
+        conditions.push(Condition {
+            location: location.clone(),
+            enclosing,
+            taken: test,
+            active: enclosing && test,
+            has_else: false,
+        });
+        Ok(())
+    }
+
+    fn condition(&self, text: &str, location: &Location) -> Result<bool, Error> {
+        self.evaluate(text)
+            .map(|value| value != 0)
+            .map_err(|e| format!("{location}: {e}"))
+    }
+
+    fn evaluate(&self, text: &str) -> Result<Integer, Error> {
+        expression
+            .parse(text)
+            .map_err(|_| format!("invalid expression `{}`", text.trim()))?
+            .evaluate(&|name| {
+                self.constants
+                    .get(name)
+                    .copied()
+                    .ok_or_else(|| format!("unknown constant {name}"))
+            })
+    }
+
+    fn include(&mut self, path: &str, location: &Location, depth: usize) -> Result<(), Error> {
+        let file = [location.directory()]
+            .iter()
+            .chain(self.include_paths.iter())
+            .map(|directory| directory.join(path))
+            .find(|file| file.is_file())
+            .ok_or_else(|| format!("{location}: cannot find include file {path}"))?;
+        let canonical = file.canonicalize().map_err(|e| e.to_string())?;
+
+        if let Some(start) = self.including.iter().position(|(c, _)| *c == canonical) {
+            let cycle: Vec<_> = self.including[start..]
+                .iter()
+                .map(|(_, file)| file.display().to_string())
+                .chain([file.display().to_string()])
+                .collect();
+            return Err(format!("{location}: include cycle {}", cycle.join(" -> ")));
+        }
+
+        let lines = SourceLine::read_file(&file).map_err(|e| format!("{location}: {e}"))?;
+        self.including.push((canonical, Rc::from(file.as_path())));
+        self.lines(lines, depth)?;
+        self.including.pop();
+        Ok(())
+    }
+
+    /// Takes the body of a macro from `lines`, up to `.endm`.
+    fn definition(
+        &self,
+        parameters: &str,
+        location: &Location,
+        lines: &mut impl Iterator<Item = SourceLine>,
+    ) -> Result<Macro, Error> {
+        let parameters = parameters
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|parameter| !parameter.is_empty())
+            .map(|parameter| match identifier.parse(parameter) {
+                Ok(parameter) => Ok(parameter.to_string()),
+                Err(_) => Err(format!("{location}: invalid macro parameter `{parameter}`")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut body = Vec::new();
+        for line in lines.by_ref() {
+            let text = strip_comment(line.text.trim());
+            if text == ".endm" {
+                return Ok(Macro {
+                    parameters,
+                    body,
+                    location: location.clone(),
+                });
+            }
+            if macro_definition.parse(text).is_ok() {
+                return Err(format!(
+                    "{}: macros cannot be defined inside a macro",
+                    line.location
+                ));
+            }
+            body.push(line);
+        }
+        Err(format!("{location}: .macro without .endm"))
+    }
+
+    /// The macro and its arguments when `text` calls a macro.
+    fn call<'s>(&self, text: &'s str) -> Option<(&'s str, &'s str)> {
+        let (name, arguments) = (identifier, opt(preceded(space1, rest))).parse(text).ok()?;
+        self.macros
+            .contains_key(name)
+            .then(|| (name, arguments.unwrap_or_default()))
+    }
+
+    fn expand(
+        &mut self,
+        name: &str,
+        arguments: &str,
+        call: &Location,
+        depth: usize,
+    ) -> Result<Vec<SourceLine>, Error> {
+        self.expansions += 1;
+        let unique = self.expansions.to_string();
+        let definition = &self.macros[name];
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(format!(
+                "{call}: macro {name} is expanded more than {MAX_EXPANSION_DEPTH} levels deep"
+            ));
+        }
+        let arguments = split_arguments(arguments);
+        if arguments.len() != definition.parameters.len() {
+            return Err(format!(
+                "{call}: macro {name} (defined on {}) takes {} arguments, got {}",
+                definition.location,
+                definition.parameters.len(),
+                arguments.len()
+            ));
+        }
+
+        let expansion = Rc::new(Expansion {
+            name: name.to_string(),
+            definition: definition.location.clone(),
+            call: call.clone(),
+        });
+        let substitutions: BTreeMap<&str, &str> = definition
+            .parameters
+            .iter()
+            .map(String::as_str)
+            .zip(arguments)
+            .chain([("@", unique.as_str())])
+            .collect();
+
+        Ok(definition
+            .body
+            .iter()
+            .map(|line| SourceLine {
+                text: substitute(&line.text, &substitutions),
+                location: Location {
+                    expansion: Some(expansion.clone()),
+                    ..line.location.clone()
+                },
+            })
+            .collect())
+    }
+}
+
+/// `.macro name a, b`, gives the name and the parameters.
+fn macro_definition<'s>(input: &mut &'s str) -> PResult<(&'s str, &'s str)> {
+    preceded((".macro", space1), (identifier, preceded(space0, rest))).parse_next(input)
+}
+
+fn conditional_directive<'s>(input: &mut &'s str) -> PResult<Directive<'s>> {
+    alt((
+        preceded((".ifdef", space1), identifier).map(Directive::Ifdef),
+        preceded((".ifndef", space1), identifier).map(Directive::Ifndef),
+        preceded(".if", alt((preceded(space1, rest), eof))).map(Directive::If),
+        preceded(".elif", alt((preceded(space1, rest), eof))).map(Directive::Elif),
+        ".else".value(Directive::Else),
+        ".endif".value(Directive::Endif),
+    ))
+    .parse_next(input)
+}
+
+/// `.include "path"`, gives the path.
+fn include_directive<'s>(input: &mut &'s str) -> PResult<&'s str> {
+    preceded(
+        (".include", space1),
+        delimited('"', take_till(1.., '"'), '"'),
+    )
+    .parse_next(input)
+}
+
+/// Splits on the commas that are not inside quotes, parentheses or brackets.
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    let arguments = arguments.trim();
+    if arguments.is_empty() {
+        return Vec::new();
+    }
+
+    let mut result = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in arguments.char_indices() {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', Some(_)) => escaped = true,
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => (),
+            ('"' | '\'', None) => quote = Some(c),
+            ('(' | '[', None) => depth += 1,
+            (')' | ']', None) => depth = depth.saturating_sub(1),
+            (',', None) if depth == 0 => {
+                result.push(arguments[start..index].trim());
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    result.push(arguments[start..].trim());
+    result
+}
+
+/// Replaces `\name` with the substitution for `name`, other backslashes are kept.
+fn substitute(text: &str, substitutions: &BTreeMap<&str, &str>) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('\\') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        let mut name = rest;
+        let found = if rest.starts_with('@') {
+            Some("@")
+        } else {
+            identifier.parse_next(&mut name).ok()
+        };
+        match found.and_then(|found| Some((found, substitutions.get(found)?))) {
+            Some((found, value)) => {
+                output.push_str(value);
+                rest = &rest[found.len()..];
+            }
+            None => output.push('\\'),
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+fn preprocess(input: &str) -> Result<Vec<(usize, String)>, Error> {
+    let lines = SourceLine::read(input.as_bytes(), None)?;
+    Ok(Preprocessor::default()
+        .process(lines)?
+        .into_iter()
+        .map(|line| (line.location.line, line.text.trim().to_string()))
+        .collect())
+}
+
+#[test]
+fn expand_macros() {
+    let input = r#".macro print_char c
+    mov [:char] \c
+    push :char
+    func :print
+.endm
+.macro twice a, b ; prints both
+    print_char \a
+skip\@:
+    print_char \b
+.endm ; twice
+    twice #'a', #(' ' + 1) ; a and !
+    twice #'\n', r1"#;
+
+    assert_eq!(
+        preprocess(input).unwrap(),
+        vec![
+            (2, String::from("mov [:char] #'a'")),
+            (3, String::from("push :char")),
+            (4, String::from("func :print")),
+            (8, String::from("skip1:")),
+            (2, String::from("mov [:char] #(' ' + 1)")),
+            (3, String::from("push :char")),
+            (4, String::from("func :print")),
+            (2, String::from("mov [:char] #'\\n'")),
+            (3, String::from("push :char")),
+            (4, String::from("func :print")),
+            (8, String::from("skip4:")),
+            (2, String::from("mov [:char] r1")),
+            (3, String::from("push :char")),
+            (4, String::from("func :print")),
+        ]
+    );
+}
+
+#[test]
+fn macro_errors() {
+    let error = preprocess(".macro one a\nmov r0 \\a\n.endm\none #1, #2").unwrap_err();
+    assert_eq!(
+        "line 4: macro one (defined on line 1) takes 1 arguments, got 2",
+        error
+    );
+
+    let error = preprocess(".macro one\nmov r0 #1").unwrap_err();
+    assert_eq!("line 1: .macro without .endm", error);
+
+    let error = preprocess(".macro loop\nloop\n.endm\nloop").unwrap_err();
+    assert!(error.starts_with("line 2 in macro loop (defined on line 1) expanded on line 2"));
+    assert!(error.ends_with("expanded on line 4: macro loop is expanded more than 64 levels deep"));
+}
+
+#[test]
+fn split_macro_arguments() {
+    assert_eq!(
+        vec!["\"a,b\"", "[r1 + 2]", "#(MAX(1, 2))", "','"],
+        split_arguments(" \"a,b\", [r1 + 2] ,#(MAX(1, 2)), ','")
+    );
+    assert!(split_arguments(" ").is_empty());
+}
+
+#[test]
+fn include_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("lib")).unwrap();
+    std::fs::create_dir(dir.path().join("shared")).unwrap();
+    std::fs::write(dir.path().join("main.s"), "mov r0 #1\n.include \"lib/a.s\"").unwrap();
+    std::fs::write(
+        dir.path().join("lib/a.s"),
+        "\n.include \"b.s\" ; next to a.s\nmov r0 #2",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("lib/b.s"), ".include \"c.s\"").unwrap();
+    std::fs::write(dir.path().join("shared/c.s"), "mov r0 #3").unwrap();
+
+    let options = Options::default().with_include_path(dir.path().join("shared"));
+    let main = dir.path().join("main.s");
+    let lines = Preprocessor::new(&options)
+        .process(SourceLine::read_file(&main).unwrap())
+        .unwrap();
+    let lines: Vec<_> = lines
+        .iter()
+        .filter(|line| !line.text.is_empty())
+        .map(|line| (line.location.to_string(), line.text.as_str()))
+        .collect();
+
+    let file = |path: &str| dir.path().join(path).display().to_string();
+    assert_eq!(
+        lines,
+        vec![
+            (format!("line 1 of {}", file("main.s")), "mov r0 #1"),
+            (format!("line 1 of {}", file("shared/c.s")), "mov r0 #3"),
+            (format!("line 3 of {}", file("lib/a.s")), "mov r0 #2"),
+        ]
+    );
+
+    let error = Preprocessor::default()
+        .process(SourceLine::read_file(&main).unwrap())
+        .unwrap_err();
+    assert_eq!(
+        format!(
+            "line 1 of {}: cannot find include file c.s",
+            file("lib/b.s")
+        ),
+        error
+    );
+}
+
+#[test]
+fn include_cycles() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.s"), ".include \"b.s\"").unwrap();
+    std::fs::write(dir.path().join("b.s"), "\n.include \"a.s\"").unwrap();
+
+    let a = dir.path().join("a.s");
+    let error = Preprocessor::default()
+        .process(SourceLine::read_file(&a).unwrap())
+        .unwrap_err();
+
+    let file = |path: &str| dir.path().join(path).display().to_string();
+    assert_eq!(
+        format!(
+            "line 2 of {}: include cycle {} -> {} -> {}",
+            file("b.s"),
+            file("a.s"),
+            file("b.s"),
+            file("a.s")
+        ),
+        error
+    );
+}
+
+#[test]
+fn conditional_assembly() {
+    let input = r#".equ SIZE 4
+.if SIZE > 2 ; more than a pair
+big
+.elif SIZE == 2
+two
+.else
+small
+.endif ; size
+.if SIZE - 4
+.ifdef SIZE
+nested
+.endif
+.elif SIZE & 4
+four
+.endif
+.ifndef DEBUG
+.equ DEBUG 0
+.endif
+.if DEBUG
+debug
+.else ; not debug
+release
+.endif"#;
+
+    let lines: Vec<_> = preprocess(input)
+        .unwrap()
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect();
+    assert_eq!(
+        vec![".equ SIZE 4", "big", "four", ".equ DEBUG 0", "release"],
+        lines
+    );
+
+    let options = Options::default().with_define("DEBUG", 1);
+    let lines = SourceLine::read(input.as_bytes(), None).unwrap();
+    let lines: Vec<_> = Preprocessor::new(&options)
+        .process(lines)
+        .unwrap()
+        .into_iter()
+        .map(|line| line.text)
+        .collect();
+    assert_eq!(vec![".equ SIZE 4", "big", "four", "debug"], lines);
+
+    let input = "SIZE = 4 ; words\n.equ N 3 ; count\n.if SIZE + N == 7\nseven\n.endif";
+    let lines: Vec<_> = preprocess(input)
+        .unwrap()
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect();
+    assert_eq!(vec!["SIZE = 4 ; words", ".equ N 3 ; count", "seven"], lines);
+}
+
+#[test]
+fn conditional_errors() {
+    let error = preprocess(".if 1\nmov r0 #1").unwrap_err();
+    assert_eq!("line 1: .if without .endif", error);
+
+    let error = preprocess(".if 1\n.else\n.else\n.endif").unwrap_err();
+    assert_eq!("line 3: .else after .else", error);
+
+    let error = preprocess(".elif 1").unwrap_err();
+    assert_eq!("line 1: .elif without .if", error);
+
+    let error = preprocess("start:\n.if start\n.endif").unwrap_err();
+    assert_eq!("line 2: unknown constant start", error);
+
+    let error = preprocess(".if\n.endif").unwrap_err();
+    assert_eq!("line 1: .if needs an expression", error);
+
+    let error = preprocess(".if 0\n.elif ; nothing\n.endif").unwrap_err();
+    assert_eq!("line 2: .elif needs an expression", error);
+
+    // Conditions in lines that are left out are not evaluated
+    assert!(preprocess(".if 0\n.if start\n.endif\n.endif").is_ok());
+}
@@ -0,0 +1,163 @@
+use shitty_types::{Heap, Integer, Literal, MAX_HEAP_ENTRY_SIZE};
+use std::collections::BTreeMap;
+
+use crate::{Fault, RuntimeError};
+
+/// Counters for heap entries created at runtime with `alloc`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeapStats {
+    pub allocations: usize,
+    pub reallocations: usize,
+    pub frees: usize,
+    pub peak_live: usize,
+}
+
+/// A heap entry that was allocated but never freed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leak {
+    pub handle: Integer,
+    pub label: Integer,
+    pub length: usize,
+}
+
+/// Bookkeeping for heap entries created with `alloc`, entries declared with `db` are not tracked.
+#[derive(Debug, Clone, Default)]
+pub struct Allocations {
+    live: BTreeMap<Integer, Integer>,
+    free_slots: Vec<Integer>,
+    stats: HeapStats,
+}
+
+impl Allocations {
+    /// Reuses a freed heap slot when there is one.
+    pub fn take_free_slot(&mut self) -> Option<Integer> {
+        self.free_slots.pop()
+    }
+
+    pub fn allocated(&mut self, handle: Integer, label: Integer) {
+        self.live.insert(handle, label);
+        self.stats.allocations += 1;
+        self.stats.peak_live = self.stats.peak_live.max(self.live.len());
+    }
+
+    pub fn reallocated(&mut self) {
+        self.stats.reallocations += 1;
+    }
+
+    pub fn is_live(&self, handle: Integer) -> bool {
+        self.live.contains_key(&handle)
+    }
+
+    pub fn is_freed(&self, handle: Integer) -> bool {
+        self.free_slots.contains(&handle)
+    }
+
+    pub fn freed(&mut self, handle: Integer) {
+        if self.live.remove(&handle).is_some() {
+            self.free_slots.push(handle);
+            self.stats.frees += 1;
+        }
+    }
+
+    pub fn stats(&self) -> &HeapStats {
+        &self.stats
+    }
+
+    pub fn live(&self) -> impl Iterator<Item = (Integer, Integer)> + '_ {
+        self.live.iter().map(|(handle, label)| (*handle, *label))
+    }
+}
+
+/// The heap as host functions see it, entries are checked like heap operands of instructions.
+pub struct HostHeap<'a> {
+    heap: &'a mut Heap,
+    allocations: &'a Allocations,
+}
+
+impl<'a> HostHeap<'a> {
+    pub fn new(heap: &'a mut Heap, allocations: &'a Allocations) -> Self {
+        HostHeap { heap, allocations }
+    }
+
+    /// The entry at `handle`, a handle that is freed or out of range raises a heap fault.
+    pub fn entry(&self, handle: Integer) -> Result<&Literal, RuntimeError> {
+        self.heap
+            .get(handle as usize)
+            .filter(|_| !self.allocations.is_freed(handle))
+            .ok_or_else(|| {
+                RuntimeError::Fault(
+                    Fault::InvalidHeapAccess,
+                    format!("invalid heap handle {handle}"),
+                )
+            })
+    }
+
+    /// Replaces the entry at `handle`, data longer than `MAX_HEAP_ENTRY_SIZE` raises a heap fault.
+    pub fn store(&mut self, handle: Integer, data: Literal) -> Result<(), RuntimeError> {
+        self.entry(handle)?;
+        if data.len() > MAX_HEAP_ENTRY_SIZE {
+            return Err(RuntimeError::Fault(
+                Fault::InvalidHeapAccess,
+                format!(
+                    "heap entry size {} is larger than the maximum of {MAX_HEAP_ENTRY_SIZE}",
+                    data.len()
+                ),
+            ));
+        }
+        self.heap[handle as usize] = data;
+        Ok(())
+    }
+
+    /// Changes the words of the entry at `handle` in place, its length stays the same.
+    pub fn update(
+        &mut self,
+        handle: Integer,
+        f: impl FnOnce(&mut [Integer]),
+    ) -> Result<(), RuntimeError> {
+        self.entry(handle)?;
+        f(&mut self.heap[handle as usize]);
+        Ok(())
+    }
+}
+
+#[test]
+fn allocation_bookkeeping() {
+    let mut allocations = Allocations::default();
+
+    allocations.allocated(0, 10);
+    allocations.allocated(1, 11);
+    allocations.freed(0);
+    allocations.freed(0);
+
+    assert_eq!(Some(0), allocations.take_free_slot());
+    assert_eq!(None, allocations.take_free_slot());
+    assert!(allocations.is_live(1));
+    assert_eq!(vec![(1, 11)], allocations.live().collect::<Vec<_>>());
+    assert_eq!(
+        &HeapStats {
+            allocations: 2,
+            reallocations: 0,
+            frees: 1,
+            peak_live: 2,
+        },
+        allocations.stats()
+    );
+}
+
+#[test]
+fn host_heap_checks_entries() {
+    let mut heap = vec![vec![1], vec![2]];
+    let mut allocations = Allocations::default();
+    allocations.allocated(1, 11);
+    allocations.freed(1);
+    let mut heap = HostHeap::new(&mut heap, &allocations);
+
+    assert_eq!(&vec![1], heap.entry(0).unwrap());
+    assert!(heap.entry(1).is_err());
+    assert!(heap.entry(2).is_err());
+    assert!(heap.store(1, vec![3]).is_err());
+    assert!(heap.store(0, vec![0; MAX_HEAP_ENTRY_SIZE + 1]).is_err());
+    heap.store(0, vec![4, 5]).unwrap();
+    heap.update(0, |data| data[1] = 6).unwrap();
+    assert_eq!(&vec![4, 6], heap.entry(0).unwrap());
+}
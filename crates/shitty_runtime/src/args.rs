@@ -0,0 +1,109 @@
+use shitty_types::{hash_label, Integer};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::strings::{encode_string, pop};
+use crate::{decode_heap_binary_to_string, ExternalFunction};
+
+/// Host functions giving the program access to its command-line arguments.
+pub fn argument_functions(args: Vec<String>) -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
+    let mut functions: BTreeMap<Integer, Box<dyn ExternalFunction>> = BTreeMap::new();
+    let args = Rc::new(args);
+
+    // -> number of arguments
+    let count = args.len() as Integer;
+    let argc: Box<dyn ExternalFunction> = Box::new(move |_, stack| {
+        stack.push(count);
+        Ok(())
+    });
+    functions.insert(hash_label("argc"), argc);
+
+    // push :dest, push index -> 1 and dest = argument, or 0 if there is no such argument
+    let argv: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let index = pop(stack)?;
+        let dest = pop(stack)?;
+        match args.get(index as usize) {
+            Some(arg) => {
+                heap.store(dest, encode_string(arg))?;
+                stack.push(1);
+            }
+            None => stack.push(0),
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("argv"), argv);
+
+    functions
+}
+
+/// Host functions giving the program the environment variables in `variables`.
+///
+/// Only these are visible, the caller decides which variables of the host to pass on.
+pub fn environment_functions(
+    variables: BTreeMap<String, String>,
+) -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
+    let mut functions: BTreeMap<Integer, Box<dyn ExternalFunction>> = BTreeMap::new();
+
+    // push :dest, push :name -> 1 and dest = value, or 0 if the variable is not available
+    let getenv: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let name = pop(stack)?;
+        let dest = pop(stack)?;
+        let name = decode_heap_binary_to_string(heap.entry(name)?).map_err(|e| e.to_string())?;
+        match variables.get(&name) {
+            Some(value) => {
+                heap.store(dest, encode_string(value))?;
+                stack.push(1);
+            }
+            None => stack.push(0),
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("getenv"), getenv);
+
+    functions
+}
+
+#[test]
+fn argument_functions_test() {
+    let functions = argument_functions(vec![String::from("a"), String::from("bc")]);
+    let mut heap = vec![vec![]];
+    let allocations = crate::Allocations::default();
+    let mut heap = crate::HostHeap::new(&mut heap, &allocations);
+    let mut stack = vec![];
+
+    functions[&hash_label("argc")](&mut heap, &mut stack).unwrap();
+    assert_eq!(vec![2], stack);
+    stack.clear();
+
+    stack.extend([0, 1]);
+    functions[&hash_label("argv")](&mut heap, &mut stack).unwrap();
+    assert_eq!(vec![1], stack);
+    assert_eq!(&encode_string("bc"), heap.entry(0).unwrap());
+    stack.clear();
+
+    stack.extend([0, 2]);
+    functions[&hash_label("argv")](&mut heap, &mut stack).unwrap();
+    assert_eq!(vec![0], stack);
+}
+
+#[test]
+fn environment_functions_test() {
+    let functions = environment_functions(BTreeMap::from([(
+        String::from("HOME"),
+        String::from("/home/shitty"),
+    )]));
+    let mut heap = vec![vec![], encode_string("HOME"), encode_string("PATH")];
+    let allocations = crate::Allocations::default();
+    let mut heap = crate::HostHeap::new(&mut heap, &allocations);
+    let mut stack = vec![];
+
+    stack.extend([0, 1]);
+    functions[&hash_label("getenv")](&mut heap, &mut stack).unwrap();
+    assert_eq!(vec![1], stack);
+    assert_eq!(&encode_string("/home/shitty"), heap.entry(0).unwrap());
+    stack.clear();
+
+    stack.extend([0, 2]);
+    functions[&hash_label("getenv")](&mut heap, &mut stack).unwrap();
+    assert_eq!(vec![0], stack);
+}
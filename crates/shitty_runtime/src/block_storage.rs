@@ -0,0 +1,159 @@
+use shitty_types::{Error, Integer};
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::Device;
+
+pub const BLOCK_STORAGE_ADDRESS: Integer = 0xFFFF_3000;
+/// Bytes in a sector, the image is read and written in whole sectors.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Writing a command here starts it, reading gives the status of the last command.
+pub const BLOCK_COMMAND: Integer = 0;
+/// The sector the next command works on.
+pub const BLOCK_SECTOR: Integer = 1;
+/// Number of sectors in the image, read only.
+pub const BLOCK_SECTOR_COUNT: Integer = 2;
+/// Start of the sector buffer, one byte per word.
+pub const BLOCK_BUFFER: Integer = 8;
+
+pub const BLOCK_READ: Integer = 1;
+pub const BLOCK_WRITE: Integer = 2;
+
+pub const BLOCK_OK: Integer = 0;
+pub const BLOCK_ERROR: Integer = 1;
+
+trait Image: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Image for T {}
+
+struct State {
+    image: Box<dyn Image>,
+    sectors: Integer,
+    sector: Integer,
+    status: Integer,
+    buffer: [u8; SECTOR_SIZE],
+}
+
+impl State {
+    fn run(&mut self, command: Integer) -> Result<(), Error> {
+        if self.sector >= self.sectors {
+            return Err(format!("sector {} out of range", self.sector));
+        }
+        let position = self.sector * SECTOR_SIZE as Integer;
+        self.image
+            .seek(SeekFrom::Start(position))
+            .map_err(|e| e.to_string())?;
+        match command {
+            BLOCK_READ => self.image.read_exact(&mut self.buffer),
+            BLOCK_WRITE => self
+                .image
+                .write_all(&self.buffer)
+                .and_then(|_| self.image.flush()),
+            other => return Err(format!("invalid block storage command {other}")),
+        }
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// A disk that reads and writes whole sectors of an image through a sector buffer.
+///
+/// A program sets the sector, fills the buffer and writes `BLOCK_WRITE` to the
+/// command register, or writes `BLOCK_READ` and then reads the buffer. A failing
+/// command does not trap, it sets the status to `BLOCK_ERROR`.
+#[derive(Clone)]
+pub struct BlockStorage(Rc<RefCell<State>>);
+
+impl BlockStorage {
+    /// Uses `image` as the disk, any bytes after the last whole sector are not used.
+    pub fn new(mut image: impl Read + Write + Seek + 'static) -> Result<Self, Error> {
+        let length = image.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        Ok(BlockStorage(Rc::new(RefCell::new(State {
+            image: Box::new(image),
+            sectors: length / SECTOR_SIZE as Integer,
+            sector: 0,
+            status: BLOCK_OK,
+            buffer: [0; SECTOR_SIZE],
+        }))))
+    }
+
+    /// Opens an image file on the host for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("opening disk image {}: {e}", path.display()))?;
+        BlockStorage::new(file)
+    }
+}
+
+impl Device for BlockStorage {
+    fn size(&self) -> Integer {
+        BLOCK_BUFFER + SECTOR_SIZE as Integer
+    }
+
+    fn read(&mut self, offset: Integer) -> Result<Integer, Error> {
+        let state = self.0.borrow();
+        match offset {
+            BLOCK_COMMAND => Ok(state.status),
+            BLOCK_SECTOR => Ok(state.sector),
+            BLOCK_SECTOR_COUNT => Ok(state.sectors),
+            offset if offset >= BLOCK_BUFFER => {
+                Ok(state.buffer[(offset - BLOCK_BUFFER) as usize] as Integer)
+            }
+            _ => Err(format!("block storage offset {offset} is not readable")),
+        }
+    }
+
+    fn write(&mut self, offset: Integer, value: Integer) -> Result<(), Error> {
+        let mut state = self.0.borrow_mut();
+        match offset {
+            BLOCK_COMMAND => {
+                state.status = match state.run(value) {
+                    Ok(()) => BLOCK_OK,
+                    Err(_) => BLOCK_ERROR,
+                };
+            }
+            BLOCK_SECTOR => state.sector = value,
+            offset if offset >= BLOCK_BUFFER => {
+                let byte = u8::try_from(value).map_err(|_| format!("invalid byte {value}"))?;
+                state.buffer[(offset - BLOCK_BUFFER) as usize] = byte;
+            }
+            _ => return Err(format!("block storage offset {offset} is not writable")),
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn read_and_write_sectors() {
+    let mut image = vec![0; SECTOR_SIZE * 2 + 10];
+    image[SECTOR_SIZE] = 7;
+    let mut disk = BlockStorage::new(std::io::Cursor::new(image)).unwrap();
+
+    assert_eq!(2, disk.read(BLOCK_SECTOR_COUNT).unwrap());
+
+    disk.write(BLOCK_SECTOR, 1).unwrap();
+    disk.write(BLOCK_COMMAND, BLOCK_READ).unwrap();
+    assert_eq!(BLOCK_OK, disk.read(BLOCK_COMMAND).unwrap());
+    assert_eq!(7, disk.read(BLOCK_BUFFER).unwrap());
+
+    disk.write(BLOCK_BUFFER + 1, 42).unwrap();
+    disk.write(BLOCK_SECTOR, 0).unwrap();
+    disk.write(BLOCK_COMMAND, BLOCK_WRITE).unwrap();
+    disk.write(BLOCK_BUFFER, 0).unwrap();
+    disk.write(BLOCK_BUFFER + 1, 0).unwrap();
+    disk.write(BLOCK_COMMAND, BLOCK_READ).unwrap();
+    assert_eq!(7, disk.read(BLOCK_BUFFER).unwrap());
+    assert_eq!(42, disk.read(BLOCK_BUFFER + 1).unwrap());
+
+    disk.write(BLOCK_SECTOR, 2).unwrap();
+    disk.write(BLOCK_COMMAND, BLOCK_READ).unwrap();
+    assert_eq!(BLOCK_ERROR, disk.read(BLOCK_COMMAND).unwrap());
+    assert!(disk.write(BLOCK_BUFFER, 256).is_err());
+}
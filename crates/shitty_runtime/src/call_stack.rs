@@ -0,0 +1,120 @@
+use shitty_types::{Error, Integer};
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// An active call: where it came from and which label it jumped to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub return_address: Integer,
+    pub function: Integer,
+}
+
+/// Return addresses live here instead of on the data stack, so `push`/`pop` cannot corrupt them.
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    max_depth: usize,
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        CallStack::new(DEFAULT_MAX_CALL_DEPTH)
+    }
+}
+
+impl CallStack {
+    pub fn new(max_depth: usize) -> Self {
+        CallStack {
+            frames: Vec::new(),
+            max_depth,
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn push(&mut self, frame: Frame) -> Result<(), Error> {
+        if self.frames.len() >= self.max_depth {
+            return Err(format!(
+                "stack overflow: maximum call depth of {} exceeded",
+                self.max_depth
+            ));
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<Frame, Error> {
+        self.frames
+            .pop()
+            .ok_or_else(|| String::from("call stack underflow: return without call"))
+    }
+
+    /// Formats the active calls, innermost first.
+    pub fn backtrace(&self, program_counter: Integer) -> String {
+        let mut s = String::from("backtrace:");
+        let mut line = program_counter;
+        for (index, frame) in self.frames.iter().rev().enumerate() {
+            s.push_str(&format!(
+                "\n    {index}: line {line} in :{} (called from line {})",
+                frame.function, frame.return_address
+            ));
+            line = frame.return_address;
+        }
+        s.push_str(&format!("\n    {}: line {line}", self.frames.len()));
+        s
+    }
+}
+
+#[test]
+fn call_stack_overflow() {
+    let mut stack = CallStack::new(2);
+    let frame = Frame {
+        return_address: 1,
+        function: 2,
+    };
+
+    stack.push(frame.clone()).unwrap();
+    stack.push(frame.clone()).unwrap();
+    assert!(stack.push(frame.clone()).is_err());
+
+    assert_eq!(frame, stack.pop().unwrap());
+    assert_eq!(frame, stack.pop().unwrap());
+    assert!(stack.pop().is_err());
+}
+
+#[test]
+fn call_stack_backtrace() {
+    let mut stack = CallStack::default();
+    stack
+        .push(Frame {
+            return_address: 1,
+            function: 42,
+        })
+        .unwrap();
+    stack
+        .push(Frame {
+            return_address: 6,
+            function: 43,
+        })
+        .unwrap();
+
+    let expected = "backtrace:
+    0: line 9 in :43 (called from line 6)
+    1: line 6 in :42 (called from line 1)
+    2: line 1";
+    assert_eq!(expected, stack.backtrace(9));
+}
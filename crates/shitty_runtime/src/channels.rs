@@ -0,0 +1,185 @@
+use shitty_types::{hash_label, Error, Integer, Literal, Stack};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+use crate::strings::{missing_argument, pop};
+use crate::{ExternalFunction, RunOutcome, Runtime, RuntimeError};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Message {
+    Integer(Integer),
+    Entry(Literal),
+}
+
+/// Queues of messages by channel number, clones share the same queues.
+///
+/// Give runtimes a clone of the same `Channels` with `Runtime::with_channels`
+/// to let them talk to each other.
+#[derive(Debug, Clone, Default)]
+pub struct Channels(Rc<RefCell<BTreeMap<Integer, VecDeque<Message>>>>);
+
+impl Channels {
+    fn send(&self, channel: Integer, message: Message) {
+        self.0
+            .borrow_mut()
+            .entry(channel)
+            .or_default()
+            .push_back(message);
+    }
+
+    fn has_message(&self, channel: Integer) -> bool {
+        self.0
+            .borrow()
+            .get(&channel)
+            .is_some_and(|queue| !queue.is_empty())
+    }
+
+    fn receive(&self, channel: Integer) -> Option<Message> {
+        self.0.borrow_mut().get_mut(&channel)?.pop_front()
+    }
+}
+
+fn peek(stack: &Stack) -> Result<Integer, RuntimeError> {
+    stack.last().copied().ok_or_else(missing_argument)
+}
+
+fn integer(channel: Integer, message: Message) -> Result<Integer, Error> {
+    match message {
+        Message::Integer(value) => Ok(value),
+        Message::Entry(_) => Err(format!("expected an integer on channel {channel}")),
+    }
+}
+
+fn heap_entry(channel: Integer, message: Message) -> Result<Literal, Error> {
+    match message {
+        Message::Entry(data) => Ok(data),
+        Message::Integer(_) => Err(format!("expected a heap entry on channel {channel}")),
+    }
+}
+
+/// Host functions sending messages over `channels`, receiving blocks until a message arrives.
+pub fn channel_functions(channels: Channels) -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
+    let mut functions: BTreeMap<Integer, Box<dyn ExternalFunction>> = BTreeMap::new();
+
+    // push channel, push value
+    let sender = channels.clone();
+    let send: Box<dyn ExternalFunction> = Box::new(move |_, stack| {
+        let value = pop(stack)?;
+        let channel = pop(stack)?;
+        sender.send(channel, Message::Integer(value));
+        Ok(())
+    });
+    functions.insert(hash_label("send"), send);
+
+    // push channel, push :src -> a copy of src is sent
+    let sender = channels.clone();
+    let send_entry: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let src = pop(stack)?;
+        let channel = pop(stack)?;
+        sender.send(channel, Message::Entry(heap.entry(src)?.clone()));
+        Ok(())
+    });
+    functions.insert(hash_label("send_entry"), send_entry);
+
+    // push channel -> value
+    let receiver = channels.clone();
+    let recv: Box<dyn ExternalFunction> = Box::new(move |_, stack| {
+        let channel = peek(stack)?;
+        if !receiver.has_message(channel) {
+            return Err(RuntimeError::WouldBlock);
+        }
+        pop(stack)?;
+        let message = receiver.receive(channel).expect("checked for a message");
+        stack.push(integer(channel, message)?);
+        Ok(())
+    });
+    functions.insert(hash_label("recv"), recv);
+
+    // push :dest, push channel -> dest = received entry
+    let receiver = channels.clone();
+    let recv_entry: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let channel = peek(stack)?;
+        if !receiver.has_message(channel) {
+            return Err(RuntimeError::WouldBlock);
+        }
+        pop(stack)?;
+        let dest = pop(stack)?;
+        let message = receiver.receive(channel).expect("checked for a message");
+        heap.store(dest, heap_entry(channel, message)?)?;
+        Ok(())
+    });
+    functions.insert(hash_label("recv_entry"), recv_entry);
+
+    // push channel -> value, 1 if a value was received or 0 if the channel is empty
+    let receiver = channels.clone();
+    let try_recv: Box<dyn ExternalFunction> = Box::new(move |_, stack| {
+        let channel = pop(stack)?;
+        match receiver.receive(channel) {
+            Some(message) => {
+                stack.push(integer(channel, message)?);
+                stack.push(1);
+            }
+            None => {
+                stack.push(0);
+                stack.push(0);
+            }
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("try_recv"), try_recv);
+
+    // push :dest, push channel -> 1 and dest = received entry, or 0 if the channel is empty
+    let try_recv_entry: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let channel = pop(stack)?;
+        let dest = pop(stack)?;
+        match channels.receive(channel) {
+            Some(message) => {
+                heap.store(dest, heap_entry(channel, message)?)?;
+                stack.push(1);
+            }
+            None => stack.push(0),
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("try_recv_entry"), try_recv_entry);
+
+    functions
+}
+
+/// Runs several runtimes in turns of `quantum` instructions until all of them stopped.
+///
+/// A runtime that traps stops with `RunOutcome::Trapped` like the others do.
+/// Fails on runtime errors, or when every runtime that is still running
+/// waits for a message that can never arrive.
+pub fn run_together(runtimes: &mut [Runtime], quantum: u64) -> Result<Vec<RunOutcome>, Error> {
+    let mut outcomes = vec![None; runtimes.len()];
+
+    while outcomes.iter().any(Option::is_none) {
+        let mut progressed = false;
+        for (index, rt) in runtimes.iter_mut().enumerate() {
+            for _ in 0..quantum.max(1) {
+                if outcomes[index].is_some() {
+                    break;
+                }
+                outcomes[index] = rt.step().map_err(|e| format!("program {index}: {e}"))?;
+                if rt.blocked() {
+                    break;
+                }
+                progressed = true;
+            }
+        }
+        if !progressed {
+            let waiting: Vec<_> = (0..runtimes.len())
+                .filter(|index| outcomes[*index].is_none())
+                .map(|index| index.to_string())
+                .collect();
+            return Err(format!(
+                "deadlock: every running program is waiting on a channel: {}",
+                waiting.join(", ")
+            ));
+        }
+    }
+
+    Ok(outcomes.into_iter().flatten().collect())
+}
@@ -0,0 +1,272 @@
+use shitty_types::{Error, Integer, Stack};
+use std::collections::BTreeMap;
+
+use crate::{CallStack, Flags, Random, Registers};
+
+/// The core a program starts on, the program stops when this core stops.
+pub const MAIN_CORE: Integer = 0;
+pub const DEFAULT_QUANTUM: u64 = 100;
+
+/// Decides how many instructions a core runs before the next core gets its turn.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    quantum: u64,
+    random: Option<Random>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::round_robin(DEFAULT_QUANTUM)
+    }
+}
+
+impl Scheduler {
+    /// Every core runs `quantum` instructions per turn.
+    pub fn round_robin(quantum: u64) -> Self {
+        Scheduler {
+            quantum: quantum.max(1),
+            random: None,
+        }
+    }
+
+    /// Cores take turns in the same order, but run between 1 and `max_quantum`
+    /// instructions per turn. The same seed always gives the same interleaving.
+    pub fn seeded(max_quantum: u64, seed: u64) -> Self {
+        Scheduler {
+            quantum: max_quantum.max(1),
+            random: Some(Random::new(seed)),
+        }
+    }
+
+    fn slice(&mut self) -> u64 {
+        match self.random.as_mut() {
+            Some(random) => random.next() % self.quantum + 1,
+            None => self.quantum,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoreState {
+    Ready,
+    /// Waiting in `join` for the core with this id.
+    Joining(Integer),
+    /// Waiting for a host function, for example a receive on an empty channel.
+    Blocked,
+    /// Stopped with the value of its `r0`.
+    Finished(Integer),
+}
+
+/// The context of a core that is not running, the running core lives in the runtime itself.
+#[derive(Debug, Clone)]
+pub struct Core {
+    pub registers: Registers,
+    pub flags: Flags,
+    pub program_counter: Integer,
+    pub stack: Stack,
+    pub call_stack: CallStack,
+    pub state: CoreState,
+}
+
+/// All cores of a runtime and the scheduler switching between them.
+#[derive(Debug, Clone)]
+pub struct Cores {
+    parked: BTreeMap<Integer, Core>,
+    current: Integer,
+    state: CoreState,
+    next_id: Integer,
+    scheduler: Scheduler,
+    slice: u64,
+}
+
+impl Default for Cores {
+    fn default() -> Self {
+        Cores::new(Scheduler::default())
+    }
+}
+
+impl Cores {
+    pub fn new(mut scheduler: Scheduler) -> Self {
+        Cores {
+            parked: BTreeMap::new(),
+            current: MAIN_CORE,
+            state: CoreState::Ready,
+            next_id: MAIN_CORE + 1,
+            slice: scheduler.slice(),
+            scheduler,
+        }
+    }
+
+    pub fn current(&self) -> Integer {
+        self.current
+    }
+
+    pub fn count(&self) -> usize {
+        self.parked.len() + 1
+    }
+
+    pub fn state(&self, id: Integer) -> Option<CoreState> {
+        if id == self.current {
+            return Some(self.state);
+        }
+        self.parked.get(&id).map(|core| core.state)
+    }
+
+    pub(crate) fn spawn(&mut self, core: Core) -> Integer {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.parked.insert(id, core);
+        id
+    }
+
+    /// Changes the state of the running core, it is switched out if it is no longer ready.
+    pub(crate) fn set_state(&mut self, state: CoreState) {
+        self.state = state;
+    }
+
+    fn runnable(&self, state: CoreState) -> bool {
+        match state {
+            CoreState::Ready => true,
+            CoreState::Joining(id) => matches!(self.state(id), Some(CoreState::Finished(_))),
+            CoreState::Blocked | CoreState::Finished(_) => false,
+        }
+    }
+
+    /// Whether a core other than the running one can make progress.
+    ///
+    /// Blocked cores do not count, nothing they wait for happened since they blocked.
+    pub fn others_runnable(&self) -> bool {
+        self.parked.values().any(|core| self.runnable(core.state))
+    }
+
+    /// Lets blocked cores try again, a host function may have done what they are waiting for.
+    pub(crate) fn wake(&mut self) {
+        for core in self.parked.values_mut() {
+            if core.state == CoreState::Blocked {
+                core.state = CoreState::Ready;
+            }
+        }
+    }
+
+    /// Counts an instruction of the running core, returns the core to switch to when its turn is over.
+    ///
+    /// A core that cannot be preempted keeps running until it stops or blocks.
+    pub(crate) fn next(&mut self, preemptible: bool) -> Result<Option<Integer>, Error> {
+        if self.state == CoreState::Ready {
+            self.slice = self.slice.saturating_sub(1);
+            if self.slice > 0 || !preemptible || self.parked.is_empty() {
+                return Ok(None);
+            }
+        }
+
+        let others = || {
+            self.parked
+                .range(self.current + 1..)
+                .chain(self.parked.range(..self.current))
+        };
+        // blocked cores are only tried when nothing else can run, something outside
+        // of this runtime may have sent what they are waiting for
+        let next = others()
+            .find(|(_, core)| self.runnable(core.state))
+            .or_else(|| others().find(|(_, core)| core.state == CoreState::Blocked))
+            .map(|(id, _)| *id);
+        self.slice = self.scheduler.slice();
+
+        match next {
+            Some(id) => Ok(Some(id)),
+            None if self.state == CoreState::Ready => Ok(None),
+            None if self.state == CoreState::Blocked => {
+                self.state = CoreState::Ready;
+                Ok(None)
+            }
+            None => Err(String::from("deadlock: every core is waiting in join")),
+        }
+    }
+
+    /// Parks the running core as `current` and takes out core `id` to run next.
+    pub(crate) fn switch(&mut self, id: Integer, mut current: Core) -> Core {
+        current.state = self.state;
+        self.parked.insert(self.current, current);
+
+        let mut core = self
+            .parked
+            .remove(&id)
+            .expect("switching to a core that does not exist");
+        // a joining core runs `join` again, which now finds the core finished
+        self.state = CoreState::Ready;
+        core.state = CoreState::Ready;
+        self.current = id;
+        core
+    }
+}
+
+#[cfg(test)]
+fn parked_core() -> Core {
+    Core {
+        registers: Registers::new(),
+        flags: Flags::default(),
+        program_counter: 0,
+        stack: Vec::new(),
+        call_stack: CallStack::default(),
+        state: CoreState::Ready,
+    }
+}
+
+#[test]
+fn round_robin_switches_after_quantum() {
+    let mut cores = Cores::new(Scheduler::round_robin(2));
+    let worker = cores.spawn(parked_core());
+
+    assert_eq!(None, cores.next(true).unwrap());
+    assert_eq!(Some(worker), cores.next(true).unwrap());
+
+    cores.switch(worker, parked_core());
+    assert_eq!(worker, cores.current());
+    assert_eq!(None, cores.next(false).unwrap());
+    assert_eq!(None, cores.next(false).unwrap());
+    assert_eq!(2, cores.count());
+}
+
+#[test]
+fn joining_every_core_is_a_deadlock() {
+    let mut cores = Cores::default();
+    let worker = cores.spawn(parked_core());
+    cores.set_state(CoreState::Joining(worker));
+    assert_eq!(Some(worker), cores.next(true).unwrap());
+
+    cores.switch(worker, parked_core());
+    cores.set_state(CoreState::Joining(MAIN_CORE));
+    assert!(cores.next(true).is_err());
+}
+
+#[test]
+fn blocked_core_yields() {
+    let mut cores = Cores::default();
+    let worker = cores.spawn(parked_core());
+    cores.set_state(CoreState::Blocked);
+    assert_eq!(Some(worker), cores.next(true).unwrap());
+
+    cores.switch(worker, parked_core());
+    assert!(!cores.others_runnable());
+    cores.wake();
+    assert!(cores.others_runnable());
+
+    cores.set_state(CoreState::Finished(0));
+    assert_eq!(Some(MAIN_CORE), cores.next(true).unwrap());
+    cores.switch(MAIN_CORE, parked_core());
+    cores.set_state(CoreState::Blocked);
+    assert_eq!(None, cores.next(true).unwrap());
+    assert!(!cores.others_runnable());
+}
+
+#[test]
+fn seeded_scheduler_is_reproducible() {
+    let slices = |seed| {
+        let mut scheduler = Scheduler::seeded(10, seed);
+        (0..8).map(|_| scheduler.slice()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(slices(3), slices(3));
+    assert_ne!(slices(3), slices(4));
+    assert!(slices(3).iter().all(|slice| (1..=10).contains(slice)));
+}
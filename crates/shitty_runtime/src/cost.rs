@@ -0,0 +1,123 @@
+use shitty_types::{Argument, Command, Integer};
+
+/// Cycle cost of every instruction, used to measure programs independent of host speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostTable {
+    pub basic: Integer,
+    pub multiply: Integer,
+    pub divide: Integer,
+    pub branch: Integer,
+    pub stack: Integer,
+    pub call: Integer,
+    pub function: Integer,
+    pub allocation: Integer,
+    pub memory_operand: Integer,
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        CostTable {
+            basic: 1,
+            multiply: 3,
+            divide: 10,
+            branch: 1,
+            stack: 2,
+            call: 3,
+            function: 5,
+            allocation: 5,
+            memory_operand: 2,
+        }
+    }
+}
+
+impl CostTable {
+    pub fn cost(&self, command: &Command, args: &[Argument; 2]) -> Integer {
+        let base = match command {
+            // labels are not real instructions, they only mark a location,
+            // and data was put on the heap before the program started
+            Command::Noop | Command::Label | Command::LabelledData(_) => return 0,
+            Command::Move
+            | Command::Compare
+            | Command::Add
+            | Command::Subtract
+            | Command::Load
+            | Command::Store
+            | Command::Length
+            | Command::Halt
+            | Command::Exit => self.basic,
+            Command::Multiply => self.multiply,
+            Command::Divide | Command::Modulo => self.divide,
+            Command::Branch
+            | Command::BranchEqual
+            | Command::BranchNotEqual
+            | Command::BranchGreater
+            | Command::BranchGreaterEqual
+            | Command::BranchLesser
+            | Command::BranchLesserEqual => self.branch,
+            Command::Push | Command::Pop => self.stack,
+            Command::Call | Command::Return | Command::Interrupt | Command::InterruptReturn => {
+                self.call
+            }
+            Command::SetInterruptVector
+            | Command::EnableInterrupts
+            | Command::DisableInterrupts
+            | Command::Flush
+            | Command::CompareAndSwap
+            | Command::FetchAdd => self.basic,
+            Command::Spawn | Command::Join => self.call,
+            Command::Function => self.function,
+            Command::Alloc | Command::Free | Command::Realloc => self.allocation,
+        };
+
+        let memory_operands = args.iter().filter(|arg| is_memory_operand(arg)).count() as Integer;
+
+        base + memory_operands * self.memory_operand
+    }
+}
+
+fn is_memory_operand(argument: &Argument) -> bool {
+    matches!(
+        argument,
+        Argument::HeapRef(_)
+            | Argument::HeapDeref(_, _)
+            | Argument::HeapIndex(_, _)
+            | Argument::Memory(_, _)
+    )
+}
+
+#[test]
+fn default_costs() {
+    let table = CostTable::default();
+    let none = [Argument::None, Argument::None];
+
+    assert_eq!(0, table.cost(&Command::Label, &none));
+    assert_eq!(
+        0,
+        table.cost(
+            &Command::LabelledData(0),
+            &[Argument::Literal(vec![1]), Argument::None]
+        )
+    );
+    assert_eq!(
+        1,
+        table.cost(&Command::Move, &[Argument::Register(0), Argument::Raw(1)])
+    );
+    assert_eq!(
+        3,
+        table.cost(
+            &Command::Multiply,
+            &[Argument::Register(0), Argument::Raw(1)]
+        )
+    );
+    assert_eq!(
+        10,
+        table.cost(&Command::Divide, &[Argument::Register(0), Argument::Raw(1)])
+    );
+    assert_eq!(
+        5,
+        table.cost(
+            &Command::Move,
+            &[Argument::HeapDeref(1, 0), Argument::HeapDeref(2, 1)]
+        )
+    );
+}
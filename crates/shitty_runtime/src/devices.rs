@@ -0,0 +1,278 @@
+use shitty_types::{Error, Integer};
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::{Framebuffer, Input, FRAMEBUFFER_ADDRESS};
+
+pub const CONSOLE_ADDRESS: Integer = 0xFFFF_0000;
+pub const RANDOM_ADDRESS: Integer = 0xFFFF_0010;
+pub const TICK_ADDRESS: Integer = 0xFFFF_0020;
+
+/// Value read from the console when there is no more input.
+pub const CONSOLE_EOF: Integer = Integer::MAX;
+
+/// A memory-mapped device, `ld` and `st` on its address range are forwarded to it.
+///
+/// Offsets are relative to the address the device is attached at.
+pub trait Device: CloneDevice {
+    /// Number of words the device occupies.
+    fn size(&self) -> Integer;
+
+    fn read(&mut self, offset: Integer) -> Result<Integer, Error>;
+
+    fn write(&mut self, offset: Integer, value: Integer) -> Result<(), Error>;
+
+    /// Called after every executed instruction.
+    fn step(&mut self) {}
+
+    /// Called by the `flush` instruction.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub trait CloneDevice {
+    fn clone_box(&self) -> Box<dyn Device>;
+}
+
+impl<D> CloneDevice for D
+where
+    D: Device + Clone + 'static,
+{
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+#[derive(Clone)]
+struct Mapping {
+    base: Integer,
+    device: Box<dyn Device>,
+}
+
+impl Mapping {
+    fn offset(&self, address: Integer) -> Option<Integer> {
+        address
+            .checked_sub(self.base)
+            .filter(|offset| *offset < self.device.size())
+    }
+}
+
+/// The devices attached to a runtime, addresses they do not claim go to memory.
+#[derive(Clone, Default)]
+pub struct DeviceBus {
+    mappings: Vec<Mapping>,
+}
+
+impl DeviceBus {
+    /// The console, random, tick and framebuffer devices at their default addresses.
+    pub fn standard(input: Input) -> Self {
+        let mut bus = DeviceBus::default();
+        bus.attach(CONSOLE_ADDRESS, Box::new(Console::stdio(input)));
+        bus.attach(RANDOM_ADDRESS, Box::new(Random::from_entropy()));
+        bus.attach(TICK_ADDRESS, Box::new(Ticks::default()));
+        bus.attach(FRAMEBUFFER_ADDRESS, Box::new(Framebuffer::default()));
+        bus
+    }
+
+    /// Attaches `device` at `base`, it shadows devices attached before it where they overlap.
+    pub fn attach(&mut self, base: Integer, device: Box<dyn Device>) {
+        self.mappings.retain(|mapping| mapping.base != base);
+        self.mappings.push(Mapping { base, device });
+    }
+
+    /// Reads from the device at `address`, `None` when no device is attached there.
+    pub fn read(&mut self, address: Integer) -> Option<Result<Integer, Error>> {
+        self.mappings.iter_mut().rev().find_map(|mapping| {
+            let offset = mapping.offset(address)?;
+            Some(mapping.device.read(offset))
+        })
+    }
+
+    /// Writes to the device at `address`, `None` when no device is attached there.
+    pub fn write(&mut self, address: Integer, value: Integer) -> Option<Result<(), Error>> {
+        self.mappings.iter_mut().rev().find_map(|mapping| {
+            let offset = mapping.offset(address)?;
+            Some(mapping.device.write(offset, value))
+        })
+    }
+
+    pub fn step(&mut self) {
+        for mapping in self.mappings.iter_mut() {
+            mapping.device.step();
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        for mapping in self.mappings.iter_mut() {
+            mapping.device.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Character console, offset 0 reads or writes a character and writing offset 1 prints a number.
+#[derive(Clone)]
+pub struct Console {
+    input: Input,
+    output: Rc<RefCell<Box<dyn Write>>>,
+}
+
+impl Console {
+    pub fn new(input: Input, output: impl Write + 'static) -> Self {
+        Console {
+            input,
+            output: Rc::new(RefCell::new(Box::new(output))),
+        }
+    }
+
+    pub fn stdio(input: Input) -> Self {
+        Console::new(input, std::io::stdout())
+    }
+}
+
+impl Device for Console {
+    fn size(&self) -> Integer {
+        2
+    }
+
+    fn read(&mut self, offset: Integer) -> Result<Integer, Error> {
+        match offset {
+            0 => Ok(self
+                .input
+                .read_char()?
+                .map_or(CONSOLE_EOF, |c| c as Integer)),
+            _ => Err(format!("console offset {offset} is write only")),
+        }
+    }
+
+    fn write(&mut self, offset: Integer, value: Integer) -> Result<(), Error> {
+        let mut output = self.output.borrow_mut();
+        match offset {
+            0 => {
+                let c = u32::try_from(value)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid character {value}"))?;
+                write!(output, "{c}")
+            }
+            _ => write!(output, "{value}"),
+        }
+        .and_then(|_| output.flush())
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// Pseudo-random numbers, reading offset 0 gives the next number and writing it sets the seed.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        let mut random = Random { state: 0 };
+        random.seed(seed);
+        random
+    }
+
+    pub fn from_entropy() -> Self {
+        Random::new(RandomState::new().hash_one(0))
+    }
+
+    fn seed(&mut self, seed: u64) {
+        // xorshift gets stuck on a zero state
+        self.state = if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        };
+    }
+
+    pub(crate) fn next(&mut self) -> Integer {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+impl Device for Random {
+    fn size(&self) -> Integer {
+        1
+    }
+
+    fn read(&mut self, _offset: Integer) -> Result<Integer, Error> {
+        Ok(self.next())
+    }
+
+    fn write(&mut self, _offset: Integer, value: Integer) -> Result<(), Error> {
+        self.seed(value);
+        Ok(())
+    }
+}
+
+/// Counts executed instructions, writing offset 0 sets the counter.
+#[derive(Debug, Clone, Default)]
+pub struct Ticks {
+    count: Integer,
+}
+
+impl Device for Ticks {
+    fn size(&self) -> Integer {
+        1
+    }
+
+    fn read(&mut self, _offset: Integer) -> Result<Integer, Error> {
+        Ok(self.count)
+    }
+
+    fn write(&mut self, _offset: Integer, value: Integer) -> Result<(), Error> {
+        self.count = value;
+        Ok(())
+    }
+
+    fn step(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+}
+
+#[test]
+fn bus_routes_addresses_to_devices() {
+    let mut bus = DeviceBus::default();
+    bus.attach(10, Box::new(Random::new(1)));
+    bus.attach(8, Box::new(Ticks::default()));
+    bus.attach(10, Box::new(Ticks::default()));
+
+    assert!(bus.read(9).is_none());
+    bus.write(10, 5).unwrap().unwrap();
+    bus.step();
+
+    assert_eq!(6, bus.read(10).unwrap().unwrap());
+    assert_eq!(1, bus.read(8).unwrap().unwrap());
+    assert!(bus.read(11).is_none());
+}
+
+#[test]
+fn random_is_reproducible_from_a_seed() {
+    let mut a = Random::new(42);
+    let mut b = Random::new(7);
+    b.write(0, 42).unwrap();
+
+    let numbers: Vec<_> = (0..4).map(|_| a.read(0).unwrap()).collect();
+    assert_eq!(
+        numbers,
+        (0..4).map(|_| b.read(0).unwrap()).collect::<Vec<_>>()
+    );
+    assert_ne!(numbers[0], numbers[1]);
+}
@@ -0,0 +1,37 @@
+use shitty_types::Error;
+use std::fmt;
+
+use crate::Fault;
+
+/// Why an instruction could not be executed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// A fault the program can handle, it is vectored into the handler set for it.
+    Fault(Fault, Error),
+    /// A host function cannot finish yet, the instruction is executed again later.
+    /// The function has to leave its arguments on the stack.
+    WouldBlock,
+    /// Any other error, it stops the program.
+    Error(Error),
+}
+
+impl From<Error> for RuntimeError {
+    fn from(error: Error) -> Self {
+        RuntimeError::Error(error)
+    }
+}
+
+impl From<&str> for RuntimeError {
+    fn from(error: &str) -> Self {
+        RuntimeError::Error(error.to_string())
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::Fault(_, message) | RuntimeError::Error(message) => f.write_str(message),
+            RuntimeError::WouldBlock => f.write_str("would block"),
+        }
+    }
+}
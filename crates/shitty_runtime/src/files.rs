@@ -0,0 +1,200 @@
+use shitty_types::{hash_label, Error, Integer};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::strings::{encode_string, pop};
+use crate::{decode_heap_binary_to_string, ExternalFunction, Input};
+
+pub const OPEN_READ: Integer = 0;
+pub const OPEN_WRITE: Integer = 1;
+pub const OPEN_APPEND: Integer = 2;
+
+/// Directories a program may access through the file functions, nothing is allowed by default.
+#[derive(Debug, Clone, Default)]
+pub struct FileSystemPolicy {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+impl FileSystemPolicy {
+    pub fn allow_read<P: AsRef<Path>>(mut self, directory: P) -> Result<Self, Error> {
+        self.read.push(canonical_directory(directory.as_ref())?);
+        Ok(self)
+    }
+
+    pub fn allow_write<P: AsRef<Path>>(mut self, directory: P) -> Result<Self, Error> {
+        self.write.push(canonical_directory(directory.as_ref())?);
+        Ok(self)
+    }
+
+    fn check(&self, path: &Path, write: bool) -> Result<(), Error> {
+        let allowed = if write { &self.write } else { &self.read };
+        let denied = || format!("file access denied: {}", path.display());
+
+        // resolving the full path follows symlinks, but a file that is about to be
+        // created does not exist yet so then only its directory can be resolved
+        let resolved = match path.canonicalize() {
+            Ok(resolved) => resolved,
+            Err(_) => {
+                let parent = match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                let file_name = path.file_name().ok_or_else(denied)?;
+                parent.canonicalize().map_err(|_| denied())?.join(file_name)
+            }
+        };
+
+        if allowed
+            .iter()
+            .any(|directory| resolved.starts_with(directory))
+        {
+            Ok(())
+        } else {
+            Err(denied())
+        }
+    }
+}
+
+fn canonical_directory(directory: &Path) -> Result<PathBuf, Error> {
+    directory
+        .canonicalize()
+        .map_err(|e| format!("invalid directory {}: {e}", directory.display()))
+}
+
+enum OpenFile {
+    Read(Input),
+    Write(BufWriter<File>),
+}
+
+#[derive(Default)]
+struct OpenFiles {
+    files: BTreeMap<Integer, OpenFile>,
+    next: Integer,
+}
+
+/// Host functions for file access, restricted by `policy`.
+pub fn file_functions(policy: FileSystemPolicy) -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
+    let mut functions: BTreeMap<Integer, Box<dyn ExternalFunction>> = BTreeMap::new();
+    let open_files = Rc::new(RefCell::new(OpenFiles::default()));
+
+    // push :path, push mode -> file, 1 on success or 0 if the file could not be opened
+    let files = open_files.clone();
+    let fopen: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let mode = pop(stack)?;
+        let path =
+            decode_heap_binary_to_string(heap.entry(pop(stack)?)?).map_err(|e| e.to_string())?;
+        let path = Path::new(&path);
+        policy.check(path, mode != OPEN_READ)?;
+
+        let opened = match mode {
+            OPEN_READ => {
+                File::open(path).map(|file| OpenFile::Read(Input::new(BufReader::new(file))))
+            }
+            OPEN_WRITE => File::create(path).map(|file| OpenFile::Write(BufWriter::new(file))),
+            OPEN_APPEND => OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map(|file| OpenFile::Write(BufWriter::new(file))),
+            other => return Err(format!("invalid file mode {other}").into()),
+        };
+
+        match opened {
+            Ok(file) => {
+                let mut files = files.borrow_mut();
+                let handle = files.next;
+                files.next += 1;
+                files.files.insert(handle, file);
+                stack.push(handle);
+                stack.push(1);
+            }
+            Err(_) => {
+                stack.push(0);
+                stack.push(0);
+            }
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("fopen"), fopen);
+
+    // push :dest, push file, push count -> number of characters read into dest, 0 at end of file
+    let files = open_files.clone();
+    let fread: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let count = pop(stack)?;
+        let handle = pop(stack)?;
+        let dest = pop(stack)?;
+        let files = files.borrow();
+        let Some(OpenFile::Read(input)) = files.files.get(&handle) else {
+            return Err(format!("file {handle} is not open for reading").into());
+        };
+
+        let mut data = String::new();
+        for _ in 0..count {
+            match input.read_char()? {
+                Some(c) => data.push(c),
+                None => break,
+            }
+        }
+        let data = encode_string(&data);
+        stack.push(data.len() as Integer);
+        heap.store(dest, data)?;
+        Ok(())
+    });
+    functions.insert(hash_label("fread"), fread);
+
+    // push file, push :src -> src is written to the file
+    let files = open_files.clone();
+    let fwrite: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let src = pop(stack)?;
+        let handle = pop(stack)?;
+        let mut files = files.borrow_mut();
+        let Some(OpenFile::Write(writer)) = files.files.get_mut(&handle) else {
+            return Err(format!("file {handle} is not open for writing").into());
+        };
+
+        let data = decode_heap_binary_to_string(heap.entry(src)?).map_err(|e| e.to_string())?;
+        writer
+            .write_all(data.as_bytes())
+            .map_err(|e| e.to_string().into())
+    });
+    functions.insert(hash_label("fwrite"), fwrite);
+
+    // push file
+    let fclose: Box<dyn ExternalFunction> = Box::new(move |_, stack| {
+        let handle = pop(stack)?;
+        match open_files.borrow_mut().files.remove(&handle) {
+            Some(OpenFile::Write(mut writer)) => writer.flush().map_err(|e| e.to_string().into()),
+            Some(OpenFile::Read(_)) => Ok(()),
+            None => Err(format!("file {handle} is not open").into()),
+        }
+    });
+    functions.insert(hash_label("fclose"), fclose);
+
+    functions
+}
+
+#[test]
+fn policy_denies_by_default() {
+    let dir = std::env::temp_dir();
+    let policy = FileSystemPolicy::default();
+
+    assert!(policy.check(&dir.join("file.txt"), false).is_err());
+    assert!(policy.check(&dir.join("file.txt"), true).is_err());
+}
+
+#[test]
+fn policy_allows_directories() {
+    let dir = std::env::temp_dir();
+    let policy = FileSystemPolicy::default().allow_read(&dir).unwrap();
+
+    assert!(policy.check(&dir.join("file.txt"), false).is_ok());
+    assert!(policy.check(&dir.join("file.txt"), true).is_err());
+    assert!(policy
+        .check(&dir.join("..").join("file.txt"), false)
+        .is_err());
+}
@@ -0,0 +1,170 @@
+use shitty_types::{Error, Integer};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::Device;
+
+pub const FRAMEBUFFER_ADDRESS: Integer = 0xFFFF_1000;
+pub const FRAMEBUFFER_WIDTH: usize = 64;
+pub const FRAMEBUFFER_HEIGHT: usize = 64;
+
+/// The 16 colours a pixel can have, a pixel stores an index into this table.
+pub const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xAA],
+    [0x00, 0xAA, 0x00],
+    [0x00, 0xAA, 0xAA],
+    [0xAA, 0x00, 0x00],
+    [0xAA, 0x00, 0xAA],
+    [0xAA, 0x55, 0x00],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xFF],
+    [0x55, 0xFF, 0x55],
+    [0x55, 0xFF, 0xFF],
+    [0xFF, 0x55, 0x55],
+    [0xFF, 0x55, 0xFF],
+    [0xFF, 0xFF, 0x55],
+    [0xFF, 0xFF, 0xFF],
+];
+
+/// A copy of the framebuffer contents, one palette index per pixel in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pixels: Vec<u8>,
+}
+
+impl Default for Image {
+    fn default() -> Self {
+        Image {
+            pixels: vec![0; FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT],
+        }
+    }
+}
+
+impl Image {
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= FRAMEBUFFER_WIDTH {
+            return None;
+        }
+        self.pixels.get(y * FRAMEBUFFER_WIDTH + x).copied()
+    }
+
+    /// Encodes the frame as a binary PPM image.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut image = format!("P6\n{FRAMEBUFFER_WIDTH} {FRAMEBUFFER_HEIGHT}\n255\n").into_bytes();
+        for pixel in self.pixels.iter() {
+            image.extend(PALETTE[*pixel as usize]);
+        }
+        image
+    }
+}
+
+type FlushHandler = Box<dyn FnMut(usize, &Image) -> Result<(), Error>>;
+
+#[derive(Default)]
+struct State {
+    frame: Image,
+    dirty: bool,
+    flushed: usize,
+    on_flush: Option<FlushHandler>,
+}
+
+/// A 64x64 pixel framebuffer, writing offset `y * 64 + x` sets a pixel to a palette index.
+///
+/// The `flush` instruction hands the current frame to the flush handler.
+/// Clones share the same pixels, so an embedder can keep a handle to it.
+#[derive(Clone, Default)]
+pub struct Framebuffer(Rc<RefCell<State>>);
+
+impl Framebuffer {
+    /// Called with the number of the frame and the frame on every flush.
+    pub fn on_flush(
+        self,
+        handler: impl FnMut(usize, &Image) -> Result<(), Error> + 'static,
+    ) -> Self {
+        self.0.borrow_mut().on_flush = Some(Box::new(handler));
+        self
+    }
+
+    pub fn frame(&self) -> Image {
+        self.0.borrow().frame.clone()
+    }
+
+    /// Whether pixels were written since the last flush.
+    pub fn dirty(&self) -> bool {
+        self.0.borrow().dirty
+    }
+
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut state = self.0.borrow_mut();
+        let State {
+            frame,
+            dirty,
+            flushed,
+            on_flush,
+        } = &mut *state;
+        if let Some(handler) = on_flush {
+            handler(*flushed, frame)?;
+        }
+        *flushed += 1;
+        *dirty = false;
+        Ok(())
+    }
+}
+
+impl Device for Framebuffer {
+    fn size(&self) -> Integer {
+        (FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT) as Integer
+    }
+
+    fn read(&mut self, offset: Integer) -> Result<Integer, Error> {
+        Ok(self.0.borrow().frame.pixels[offset as usize] as Integer)
+    }
+
+    fn write(&mut self, offset: Integer, value: Integer) -> Result<(), Error> {
+        let colour = u8::try_from(value)
+            .ok()
+            .filter(|colour| (*colour as usize) < PALETTE.len())
+            .ok_or_else(|| format!("invalid palette index {value}"))?;
+        let mut state = self.0.borrow_mut();
+        state.frame.pixels[offset as usize] = colour;
+        state.dirty = true;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Framebuffer::flush(self)
+    }
+}
+
+#[test]
+fn flush_hands_out_frames() {
+    let frames = Rc::new(RefCell::new(Vec::new()));
+    let handled = frames.clone();
+    let mut framebuffer = Framebuffer::default().on_flush(move |index, frame| {
+        handled.borrow_mut().push((index, frame.clone()));
+        Ok(())
+    });
+
+    framebuffer.write(65, 15).unwrap();
+    assert!(framebuffer.write(0, 16).is_err());
+    assert!(framebuffer.dirty());
+    Device::flush(&mut framebuffer).unwrap();
+
+    assert!(!framebuffer.dirty());
+    let frames = frames.borrow();
+    assert_eq!(1, frames.len());
+    assert_eq!(0, frames[0].0);
+    assert_eq!(Some(15), frames[0].1.pixel(1, 1));
+    assert_eq!(Some(0), frames[0].1.pixel(0, 1));
+    assert_eq!(None, frames[0].1.pixel(64, 0));
+}
+
+#[test]
+fn frame_to_ppm() {
+    let image = Image::default().to_ppm();
+
+    assert!(image.starts_with(b"P6\n64 64\n255\n"));
+    assert_eq!(13 + 64 * 64 * 3, image.len());
+}
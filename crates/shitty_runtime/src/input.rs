@@ -0,0 +1,159 @@
+use shitty_types::{hash_label, Error, Integer};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::rc::Rc;
+
+use crate::strings::{encode_string, pop};
+use crate::ExternalFunction;
+
+/// Status pushed by the input functions.
+pub const INPUT_EOF: Integer = 0;
+pub const INPUT_OK: Integer = 1;
+pub const INPUT_INVALID: Integer = 2;
+
+/// The input stream a program reads from, shared by the input host functions.
+#[derive(Clone)]
+pub struct Input(Rc<RefCell<Box<dyn BufRead>>>);
+
+impl Input {
+    pub fn new(reader: impl BufRead + 'static) -> Self {
+        Input(Rc::new(RefCell::new(Box::new(reader))))
+    }
+
+    pub fn stdin() -> Self {
+        Input::new(BufReader::new(std::io::stdin()))
+    }
+
+    /// Reads from `reader` from now on, everything sharing this input switches with it.
+    pub fn replace(&self, reader: impl BufRead + 'static) {
+        *self.0.borrow_mut() = Box::new(reader);
+    }
+
+    /// Reads one line without the line ending, `None` at end of input.
+    pub fn read_line(&self) -> Result<Option<String>, Error> {
+        let mut line = String::new();
+        let read = self
+            .0
+            .borrow_mut()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+        Ok(Some(line))
+    }
+
+    /// Reads one UTF-8 encoded character, `None` at end of input.
+    pub fn read_char(&self) -> Result<Option<char>, Error> {
+        let mut reader = self.0.borrow_mut();
+        let mut buffer = [0; 4];
+
+        let Some(first) = read_byte(&mut *reader)? else {
+            return Ok(None);
+        };
+        buffer[0] = first;
+        let width = match first {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            _ => 4,
+        };
+        for byte in buffer.iter_mut().take(width).skip(1) {
+            *byte = read_byte(&mut *reader)?
+                .ok_or_else(|| String::from("unexpected end of input in character"))?;
+        }
+
+        std::str::from_utf8(&buffer[..width])
+            .map_err(|e| e.to_string())
+            .map(|s| s.chars().next())
+    }
+}
+
+fn read_byte(reader: &mut dyn BufRead) -> Result<Option<u8>, Error> {
+    let byte = reader
+        .fill_buf()
+        .map_err(|e| e.to_string())?
+        .first()
+        .copied();
+    if byte.is_some() {
+        reader.consume(1);
+    }
+    Ok(byte)
+}
+
+/// Host functions reading from `input`, every one of them pushes a status last.
+pub fn input_functions(input: Input) -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
+    let mut functions: BTreeMap<Integer, Box<dyn ExternalFunction>> = BTreeMap::new();
+
+    // push :dest -> status, dest = line
+    let line_input = input.clone();
+    let readline: Box<dyn ExternalFunction> = Box::new(move |heap, stack| {
+        let dest = pop(stack)?;
+        match line_input.read_line()? {
+            Some(line) => {
+                heap.store(dest, encode_string(&line))?;
+                stack.push(INPUT_OK);
+            }
+            None => stack.push(INPUT_EOF),
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("readline"), readline);
+
+    // -> char, status
+    let char_input = input.clone();
+    let readchar: Box<dyn ExternalFunction> = Box::new(move |_, stack| {
+        match char_input.read_char()? {
+            Some(c) => {
+                stack.push(c as Integer);
+                stack.push(INPUT_OK);
+            }
+            None => {
+                stack.push(0);
+                stack.push(INPUT_EOF);
+            }
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("readchar"), readchar);
+
+    // -> integer, status
+    let readint: Box<dyn ExternalFunction> = Box::new(move |_, stack| {
+        match input.read_line()? {
+            Some(line) => match line.trim().parse::<Integer>() {
+                Ok(value) => {
+                    stack.push(value);
+                    stack.push(INPUT_OK);
+                }
+                Err(_) => {
+                    stack.push(0);
+                    stack.push(INPUT_INVALID);
+                }
+            },
+            None => {
+                stack.push(0);
+                stack.push(INPUT_EOF);
+            }
+        }
+        Ok(())
+    });
+    functions.insert(hash_label("readint"), readint);
+
+    functions
+}
+
+#[test]
+fn read_from_input() {
+    let input = Input::new(std::io::Cursor::new("hé\r\nline two\n42"));
+
+    assert_eq!(Some('h'), input.read_char().unwrap());
+    assert_eq!(Some('é'), input.read_char().unwrap());
+    assert_eq!(Some(String::new()), input.read_line().unwrap());
+    assert_eq!(Some(String::from("line two")), input.read_line().unwrap());
+    assert_eq!(Some(String::from("42")), input.read_line().unwrap());
+    assert_eq!(None, input.read_line().unwrap());
+    assert_eq!(None, input.read_char().unwrap());
+}
@@ -0,0 +1,129 @@
+use shitty_types::{Error, Integer};
+use std::collections::BTreeMap;
+
+use crate::{Flags, Registers};
+
+/// Runtime faults that can be vectored into an interrupt handler, the value is the vector number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    DivideByZero = 0,
+    InvalidHeapAccess = 1,
+    StackUnderflow = 2,
+    StackOverflow = 3,
+    InvalidMemoryAccess = 4,
+}
+
+impl Fault {
+    pub fn vector(self) -> Integer {
+        self as Integer
+    }
+}
+
+/// State saved when entering a handler and restored by `iret`.
+#[derive(Debug, Clone)]
+pub struct InterruptFrame {
+    pub vector: Integer,
+    /// The instruction `iret` continues at.
+    pub return_address: Integer,
+    pub flags: Flags,
+    pub call_depth: usize,
+    /// Whether maskable interrupts were enabled before the handler was entered.
+    pub enabled: bool,
+    /// Hardware interrupts save the registers as well, because the interrupted
+    /// code cannot know when they happen.
+    pub registers: Option<Registers>,
+}
+
+/// The interrupt vector table and the handlers that are currently running.
+///
+/// Maskable (hardware) interrupts are disabled at the start and while a handler runs.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupts {
+    vectors: BTreeMap<Integer, Integer>,
+    active: Vec<InterruptFrame>,
+    enabled: bool,
+}
+
+impl Interrupts {
+    pub fn set_vector(&mut self, vector: Integer, label: Integer) {
+        self.vectors.insert(vector, label);
+    }
+
+    /// The handler label for `vector`, unless that vector is already being handled.
+    pub fn handler(&self, vector: Integer) -> Option<Integer> {
+        if self.active.iter().any(|frame| frame.vector == vector) {
+            return None;
+        }
+        self.vectors.get(&vector).copied()
+    }
+
+    /// Saves `frame`, the interrupt flag is taken from the current state.
+    pub fn enter(&mut self, mut frame: InterruptFrame) {
+        frame.enabled = self.enabled;
+        self.enabled = false;
+        self.active.push(frame);
+    }
+
+    pub fn leave(&mut self) -> Result<InterruptFrame, Error> {
+        let frame = self
+            .active
+            .pop()
+            .ok_or_else(|| String::from("iret outside of an interrupt handler"))?;
+        self.enabled = frame.enabled;
+        Ok(frame)
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn depth(&self) -> usize {
+        self.active.len()
+    }
+}
+
+#[test]
+fn nested_vector_has_no_handler() {
+    let mut interrupts = Interrupts::default();
+    interrupts.set_vector(Fault::DivideByZero.vector(), 42);
+
+    assert_eq!(Some(42), interrupts.handler(0));
+    assert_eq!(None, interrupts.handler(1));
+
+    interrupts.enter(InterruptFrame {
+        vector: 0,
+        return_address: 3,
+        flags: Flags::default(),
+        call_depth: 0,
+        enabled: false,
+        registers: None,
+    });
+    assert_eq!(None, interrupts.handler(0));
+
+    assert_eq!(3, interrupts.leave().unwrap().return_address);
+    assert!(interrupts.leave().is_err());
+}
+
+#[test]
+fn handlers_run_with_interrupts_disabled() {
+    let mut interrupts = Interrupts::default();
+    assert!(!interrupts.enabled());
+    interrupts.set_enabled(true);
+
+    interrupts.enter(InterruptFrame {
+        vector: 32,
+        return_address: 0,
+        flags: Flags::default(),
+        call_depth: 0,
+        enabled: false,
+        registers: None,
+    });
+    assert!(!interrupts.enabled());
+
+    interrupts.leave().unwrap();
+    assert!(interrupts.enabled());
+}
@@ -1,8 +1,54 @@
+mod allocations;
+mod args;
+mod block_storage;
+mod call_stack;
+mod channels;
+mod cores;
+mod cost;
+mod devices;
+mod error;
+mod files;
+mod framebuffer;
+mod input;
+mod interrupts;
+mod memory;
+mod strings;
+mod timer;
+
+pub use allocations::{Allocations, HeapStats, HostHeap, Leak};
+pub use args::{argument_functions, environment_functions};
+pub use block_storage::{
+    BlockStorage, BLOCK_BUFFER, BLOCK_COMMAND, BLOCK_ERROR, BLOCK_OK, BLOCK_READ, BLOCK_SECTOR,
+    BLOCK_SECTOR_COUNT, BLOCK_STORAGE_ADDRESS, BLOCK_WRITE, SECTOR_SIZE,
+};
+pub use call_stack::{CallStack, Frame, DEFAULT_MAX_CALL_DEPTH};
+pub use channels::{channel_functions, run_together, Channels};
+pub use cores::{Core, CoreState, Cores, Scheduler, DEFAULT_QUANTUM, MAIN_CORE};
+pub use cost::CostTable;
+pub use devices::{
+    Console, Device, DeviceBus, Random, Ticks, CONSOLE_ADDRESS, CONSOLE_EOF, RANDOM_ADDRESS,
+    TICK_ADDRESS,
+};
+pub use error::RuntimeError;
+pub use files::{file_functions, FileSystemPolicy, OPEN_APPEND, OPEN_READ, OPEN_WRITE};
+pub use framebuffer::{
+    Framebuffer, Image, FRAMEBUFFER_ADDRESS, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH, PALETTE,
+};
+pub use input::{input_functions, Input, INPUT_EOF, INPUT_INVALID, INPUT_OK};
+pub use interrupts::{Fault, InterruptFrame, Interrupts};
+pub use memory::{Memory, DEFAULT_MEMORY_SIZE};
+pub use strings::string_functions;
+pub use timer::{Timer, TIMER_VECTOR};
+
 use educe::Educe;
-use shitty_types::{hash_label, Argument, Command, Error, Heap, Integer, Program, Stack};
+use shitty_types::{
+    data_layout, hash_label, Argument, Command, Error, Heap, Integer, Offset, Program, Stack,
+    MAX_HEAP_ENTRY_SIZE,
+};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::io::BufRead;
 use std::num::TryFromIntError;
 
 #[derive(Debug, Clone)]
@@ -10,6 +56,12 @@ pub struct Registers {
     data: [Integer; 16],
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Registers {
     pub fn new() -> Self {
         Registers { data: [0; 16] }
@@ -18,7 +70,7 @@ impl Registers {
 
 // type Function<'a> = Box<dyn Fn(&'a mut Heap, &'a mut Registers) -> Result<(), Error>>;
 
-pub trait ExternalFunction: Fn(&mut Heap, &mut Stack) -> Result<(), Error> {
+pub trait ExternalFunction: Fn(&mut HostHeap, &mut Stack) -> Result<(), RuntimeError> {
     fn clone_box<'a>(&self) -> Box<dyn 'a + ExternalFunction>
     where
         Self: 'a;
@@ -26,7 +78,7 @@ pub trait ExternalFunction: Fn(&mut Heap, &mut Stack) -> Result<(), Error> {
 
 impl<F> ExternalFunction for F
 where
-    F: Fn(&mut Heap, &mut Stack) -> Result<(), Error> + Clone,
+    F: Fn(&mut HostHeap, &mut Stack) -> Result<(), RuntimeError> + Clone,
 {
     fn clone_box<'a>(&self) -> Box<dyn 'a + ExternalFunction>
     where
@@ -50,6 +102,24 @@ pub struct Flags {
     overflow: bool,
 }
 
+/// How `Runtime::run` ended, errors that are not faults are returned as the error instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunOutcome {
+    /// `hlt` was executed.
+    Halted,
+    /// `exit` was executed with this code.
+    ///
+    /// Codes are at most 255 like process exit statuses, `exit` fails on larger ones
+    /// instead of truncating them.
+    Exited(Integer),
+    /// Execution ran past the last line of the program.
+    FellOffEnd,
+    /// The instruction limit was reached before the program stopped.
+    LimitReached,
+    /// A fault was raised with no handler set for it, the message includes the backtrace.
+    Trapped(Fault, Error),
+}
+
 #[derive(Clone, Educe)]
 #[educe(Debug)]
 pub struct Runtime {
@@ -58,22 +128,36 @@ pub struct Runtime {
     program_counter: Integer,
     program: Program,
     heap: Heap,
+    allocations: Allocations,
+    memory: Memory,
+    #[educe(Debug(ignore))]
+    devices: DeviceBus,
+    /// Read by the input functions and the standard console.
+    #[educe(Debug(ignore))]
+    input: Input,
     stack: Stack,
+    call_stack: CallStack,
+    cores: Cores,
+    interrupts: Interrupts,
+    timer: Option<Timer>,
     label_references: BTreeMap<Integer, Integer>,
     #[educe(Debug(ignore))]
     external_functions: BTreeMap<Integer, Box<dyn ExternalFunction>>,
+    cost_table: CostTable,
+    cycles: Integer,
+    instructions: Integer,
+    instruction_limit: Option<Integer>,
+    stopped: Option<RunOutcome>,
+    /// The last instruction waited for a host function and no other core can run.
+    blocked: bool,
     debug: bool,
 }
 
-pub fn default_external_functions() -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
+pub fn default_external_functions(input: Input) -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
     let mut functions = BTreeMap::new();
 
     let print_function: Box<dyn ExternalFunction> = Box::new(|heap, stack| {
-        if let Some(heap_value) = stack
-            .pop()
-            .map(|heap_id| heap.get(heap_id as usize))
-            .flatten()
-        {
+        if let Some(heap_value) = stack.pop().and_then(|heap_id| heap.entry(heap_id).ok()) {
             if let Ok(string) = decode_heap_binary_to_string(heap_value) {
                 println!("{}", string);
             } else {
@@ -84,20 +168,16 @@ pub fn default_external_functions() -> BTreeMap<Integer, Box<dyn ExternalFunctio
     });
     functions.insert(hash_label("print"), print_function);
 
-    let random_function: Box<dyn ExternalFunction> = Box::new(|_, stack| {
-        let mut buffer = [0; 8];
-        getrandom::getrandom(&mut buffer).map_err(|e| e.to_string())?;
-        let integer = Integer::from_be_bytes(buffer);
-        stack.push(integer);
-
-        Ok(())
-    });
-    functions.insert(hash_label("getrandom"), random_function);
+    functions.extend(string_functions());
+    functions.extend(input_functions(input));
+    functions.extend(argument_functions(Vec::new()));
+    functions.extend(file_functions(FileSystemPolicy::default()));
+    functions.extend(channel_functions(Channels::default()));
 
     functions
 }
 
-pub fn decode_heap_binary_to_string(item: &Vec<Integer>) -> Result<String, TryFromIntError> {
+pub fn decode_heap_binary_to_string(item: &[Integer]) -> Result<String, TryFromIntError> {
     item.iter().try_fold(String::new(), |mut string, integer| {
         if let Some(ch) = char::from_u32(u32::try_from(*integer)?) {
             string.push(ch);
@@ -108,15 +188,32 @@ pub fn decode_heap_binary_to_string(item: &Vec<Integer>) -> Result<String, TryFr
 
 impl Runtime {
     pub fn new(program: Program) -> Self {
+        let mut label_references = Self::scan_labels(&program);
+        let heap = Self::place_data(&program, &mut label_references);
+        let input = Input::stdin();
         Runtime {
             flags: Flags::default(),
             registers: Registers::new(),
-            heap: Heap::new(),
+            heap,
+            allocations: Allocations::default(),
+            memory: Memory::default(),
+            devices: DeviceBus::standard(input.clone()),
+            external_functions: default_external_functions(input.clone()),
+            input,
             stack: Vec::new(),
+            call_stack: CallStack::default(),
+            cores: Cores::default(),
+            interrupts: Interrupts::default(),
+            timer: None,
             program_counter: 0,
-            label_references: Self::scan_labels(&program),
-            external_functions: default_external_functions(),
+            label_references,
             program,
+            cost_table: CostTable::default(),
+            cycles: 0,
+            instructions: 0,
+            instruction_limit: None,
+            stopped: None,
+            blocked: false,
             debug: false,
         }
     }
@@ -126,6 +223,78 @@ impl Runtime {
         self
     }
 
+    /// Replaces the stream the input functions and the standard console read from, stdin by default.
+    ///
+    /// A console attached with `with_device` keeps reading from its own input.
+    pub fn with_input(self, reader: impl BufRead + 'static) -> Self {
+        self.input.replace(reader);
+        self
+    }
+
+    /// Attaches a memory-mapped device at `base`, replacing a device already attached there.
+    pub fn with_device(mut self, base: Integer, device: Box<dyn Device>) -> Self {
+        self.devices.attach(base, device);
+        self
+    }
+
+    /// Command-line arguments available through `argc` and `argv`.
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.external_functions.extend(argument_functions(args));
+        self
+    }
+
+    /// Environment variables available through `getenv`, none by default.
+    pub fn with_env(mut self, variables: BTreeMap<String, String>) -> Self {
+        self.external_functions
+            .extend(environment_functions(variables));
+        self
+    }
+
+    /// Directories the file functions may access, none by default.
+    pub fn with_file_system(mut self, policy: FileSystemPolicy) -> Self {
+        self.external_functions.extend(file_functions(policy));
+        self
+    }
+
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.call_stack.set_max_depth(max_call_depth);
+        self
+    }
+
+    pub fn with_memory_size(mut self, size: usize) -> Self {
+        self.memory = Memory::new(size);
+        self
+    }
+
+    /// Stops `run` with `RunOutcome::LimitReached` after this many executed instructions.
+    pub fn with_instruction_limit(mut self, limit: Integer) -> Self {
+        self.instruction_limit = Some(limit);
+        self
+    }
+
+    /// Channels shared with other runtimes, by default only the cores of this runtime share them.
+    pub fn with_channels(mut self, channels: Channels) -> Self {
+        self.external_functions.extend(channel_functions(channels));
+        self
+    }
+
+    /// How the cores started with `spawn` take turns, round-robin by default.
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.cores = Cores::new(scheduler);
+        self
+    }
+
+    /// Attaches a timer, its interrupt is only delivered while interrupts are enabled with `ei`.
+    pub fn with_timer(mut self, timer: Timer) -> Self {
+        self.timer = Some(timer);
+        self
+    }
+
+    pub fn with_cost_table(mut self, cost_table: CostTable) -> Self {
+        self.cost_table = cost_table;
+        self
+    }
+
     fn scan_labels(program: &Program) -> BTreeMap<Integer, Integer> {
         let mut label_references = BTreeMap::new();
         for (index, (command, args)) in program.iter() {
@@ -138,45 +307,116 @@ impl Runtime {
         label_references
     }
 
-    pub fn run(&mut self) -> Result<(), Error> {
+    /// Puts the data of every `db` line on the heap, so it can be used before its line runs.
+    fn place_data(program: &Program, label_references: &mut BTreeMap<Integer, Integer>) -> Heap {
+        data_layout(program)
+            .into_iter()
+            .filter_map(|entry| {
+                let (_, [Argument::Literal(data), _]) = &program[&entry.line] else {
+                    return None;
+                };
+                label_references.insert(entry.label, entry.handle);
+                Some(data.clone())
+            })
+            .collect()
+    }
+
+    pub fn run(&mut self) -> Result<RunOutcome, Error> {
+        loop {
+            if let Some(outcome) = self.step()? {
+                return Ok(outcome);
+            }
+            // nothing outside of this runtime can send to its channels
+            if self.blocked {
+                return Err(String::from(
+                    "deadlock: waiting on a channel that nothing can send to",
+                ));
+            }
+        }
+    }
+
+    /// Executes a single instruction, returns how the program ended once it has.
+    pub fn step(&mut self) -> Result<Option<RunOutcome>, Error> {
         let Some((last_line, _)) = self.program.last_key_value() else {
-            return Ok(());
+            return Ok(Some(RunOutcome::FellOffEnd));
         };
         let end = *last_line + 1;
 
-        loop {
-            match self.tick(end) {
-                Ok(true) => break,
-                Ok(false) => (),
-                Err(e) => return Err(e),
-            }
+        if self
+            .instruction_limit
+            .is_some_and(|limit| self.instructions >= limit)
+        {
+            return Ok(Some(RunOutcome::LimitReached));
+        }
+        if self.tick(end)? {
+            return Ok(Some(self.stopped.clone().unwrap_or(RunOutcome::FellOffEnd)));
         }
+        Ok(None)
+    }
 
-        Ok(())
+    /// Whether every core is waiting, for example on an empty channel, only another runtime can wake it.
+    pub fn blocked(&self) -> bool {
+        self.blocked
     }
 
     pub fn tick(&mut self, end: Integer) -> Result<bool, Error> {
-        if self.program_counter >= end {
+        if self.stopped.is_some() {
             return Ok(true);
         }
+        if self.program_counter >= end {
+            if self.cores.current() == MAIN_CORE {
+                return Ok(true);
+            }
+            self.cores.set_state(CoreState::Finished(self.output()));
+            self.schedule()?;
+            return Ok(false);
+        }
+        self.poll_timer();
 
         if let Some((command, args)) = self
             .program
             .get(&self.program_counter)
             .map(|(c, [a1, a2])| (c.clone(), [a1.clone(), a2.clone()]))
         {
-            self.apply_command(&command, &args)?;
+            self.blocked = false;
+            if let Err(error) = self.apply_command(&command, &args) {
+                match error {
+                    RuntimeError::Fault(fault, message) => {
+                        if !self.enter_interrupt(fault.vector(), self.program_counter + 1, None) {
+                            let message = self.with_backtrace(message);
+                            self.stopped = Some(RunOutcome::Trapped(fault, message));
+                            return Ok(true);
+                        }
+                    }
+                    error => return Err(self.with_backtrace(error.to_string())),
+                }
+            }
+            if self.blocked {
+                // the instruction is tried again on the next turn of this core
+                self.cores.set_state(CoreState::Blocked);
+                self.blocked = !self.cores.others_runnable();
+                self.schedule()?;
+                return Ok(false);
+            }
+            self.cycles += self.cost_table.cost(&command, &args);
+            self.instructions += 1;
+            self.devices.step();
             if self.debug {
                 self.print_registers();
             }
+            self.schedule()?;
         } else {
             self.program_counter += 1;
         };
 
-        Ok(false)
+        Ok(self.stopped.is_some())
     }
 
-    pub fn apply_command(&mut self, command: &Command, args: &[Argument; 2]) -> Result<(), Error> {
+    pub fn apply_command(
+        &mut self,
+        command: &Command,
+        args: &[Argument; 2],
+    ) -> Result<(), RuntimeError> {
         let mut increase_program_counter = true;
         match command {
             Command::Noop => (),
@@ -185,30 +425,35 @@ impl Runtime {
 
                 match args[0] {
                     Argument::Register(reg) => self.registers.data[reg as usize] = new_value,
-                    Argument::HeapRef(heap_id) => {
+                    Argument::HeapRef(_heap_id) => {
                         // self.heap
                         //     .entry(heap_id)
                         //     .and_modify(|p| *p = new_value)
                         //     .or_insert(new_value);
                         todo!("move heap");
                     }
-                    Argument::HeapDeref(label, offset) => {
-                        let heap_id = self
-                            .label_references
-                            .get(&label)
-                            .ok_or_else(|| "Invalid argument".to_string())?;
-
-                        if let Some(data) = self.heap.get_mut(*heap_id as usize) {
-                            if offset >= data.len() {
-                                let extra = offset - data.len() + 1;
-                                data.extend(std::iter::repeat(0).take(extra));
-                            }
-                            data[offset] = new_value;
-                        } else {
-                            panic!("heap not found");
+                    Argument::HeapDeref(_, _) | Argument::HeapIndex(_, _) => {
+                        let Some((label, index)) = self.heap_index(&args[0]) else {
+                            unreachable!()
+                        };
+                        if index >= MAX_HEAP_ENTRY_SIZE {
+                            return Err(RuntimeError::Fault(
+                                Fault::InvalidHeapAccess,
+                                format!(
+                                    "heap write out of bounds: index {index} of :{label} is past \
+                                     the maximum entry size of {MAX_HEAP_ENTRY_SIZE}"
+                                ),
+                            ));
+                        }
+                        let data = self.heap_entry_mut(label)?;
+
+                        if index >= data.len() {
+                            let extra = index - data.len() + 1;
+                            data.extend(std::iter::repeat_n(0, extra));
                         }
+                        data[index] = new_value;
                     }
-                    _ => return Err("Invalid argument".to_string()),
+                    _ => return Err("Invalid argument".into()),
                 }
             }
             Command::Label => {}
@@ -279,38 +524,231 @@ impl Runtime {
                 self.stack.push(value);
             }
             Command::Pop => {
-                if let Some(value) = self.stack.pop() {
-                    if let Some(pointer) = self.resolve_argument_mut(&args[0]) {
-                        *pointer = value;
-                    }
+                let value = self.stack.pop().ok_or_else(|| {
+                    RuntimeError::Fault(Fault::StackUnderflow, String::from("stack underflow"))
+                })?;
+                if let Some(pointer) = self.resolve_argument_mut(&args[0])? {
+                    *pointer = value;
                 }
             }
             Command::Call => {
-                self.stack.push(self.program_counter);
+                let function = args[0].resolve_label_or_error()?;
+                self.call_stack
+                    .push(Frame {
+                        return_address: self.program_counter,
+                        function,
+                    })
+                    .map_err(|e| RuntimeError::Fault(Fault::StackOverflow, e))?;
                 self.brancher(args)?;
             }
             Command::Function => {
                 let label = args[0].resolve_label_or_error()?;
                 if let Some(function) = self.external_functions.get(&label) {
-                    function(&mut self.heap, &mut self.stack)?;
+                    let mut heap = HostHeap::new(&mut self.heap, &self.allocations);
+                    match function(&mut heap, &mut self.stack) {
+                        Err(RuntimeError::WouldBlock) => {
+                            self.blocked = true;
+                            increase_program_counter = false;
+                        }
+                        result => {
+                            result?;
+                            self.cores.wake();
+                        }
+                    }
                 }
             }
             Command::Return => {
-                if let Some(value) = self.stack.pop() {
-                    self.program_counter = value;
+                let frame = self
+                    .call_stack
+                    .pop()
+                    .map_err(|e| RuntimeError::Fault(Fault::StackUnderflow, e))?;
+                if !matches!(
+                    self.program.get(&frame.return_address),
+                    Some((Command::Call, _))
+                ) {
+                    return Err(format!(
+                        "corrupted return address: line {} is not a call",
+                        frame.return_address
+                    )
+                    .into());
+                }
+                self.program_counter = frame.return_address;
+            }
+            Command::LabelledData(_) => {
+                // the data was put on the heap when the runtime was created
+                if !matches!(args[0], Argument::Literal(_)) {
+                    return Err("argument can only be literal".into());
+                }
+            }
+            Command::Load => {
+                let address = self.resolve_address(&args[1])?;
+                let value = self.load(address)?;
+                let Argument::Register(reg) = args[0] else {
+                    return Err("ld can only load into a register".into());
+                };
+                self.registers.data[reg as usize] = value;
+            }
+            Command::Store => {
+                let address = self.resolve_address(&args[0])?;
+                let value = self.resolve_argument_or_error(&args[1])?;
+                self.store(address, value)?;
+            }
+            Command::CompareAndSwap => {
+                let address = self.resolve_address(&args[0])?;
+                let new_value = self.resolve_argument_or_error(&args[1])?;
+                let value = self.load(address)?;
+                let swapped = value == self.registers.data[0];
+                if swapped {
+                    self.store(address, new_value)?;
                 } else {
-                    // error or ignore?
-                    // return Err("Stack underflow".to_string());
+                    self.registers.data[0] = value;
                 }
+                self.flags = Flags {
+                    equal: swapped,
+                    ..Flags::default()
+                };
             }
-            Command::LabelledData(label) => {
-                let Argument::Literal(value) = &args[0] else {
-                    return Err(String::from("argument can only be literal"));
+            Command::FetchAdd => {
+                let address = self.resolve_address(&args[0])?;
+                let Argument::Register(reg) = args[1] else {
+                    return Err("xadd can only add a register".into());
                 };
-
-                self.label_references
-                    .insert(*label, self.heap.len() as Integer);
-                self.heap.push(value.clone());
+                let value = self.load(address)?;
+                let sum = value.wrapping_add(self.registers.data[reg as usize]);
+                self.store(address, sum)?;
+                self.registers.data[reg as usize] = value;
+            }
+            Command::Spawn => {
+                let label = args[1].resolve_label_or_error()?;
+                let program_counter = *self
+                    .label_references
+                    .get(&label)
+                    .ok_or_else(|| String::from("Label not found"))?;
+                // check the destination before the core exists, a failed spawn must not leave it behind
+                if self.resolve_argument_mut(&args[0])?.is_none() {
+                    return Err("Invalid argument".into());
+                }
+                let id = self.cores.spawn(Core {
+                    registers: self.registers.clone(),
+                    flags: Flags::default(),
+                    program_counter,
+                    stack: Vec::new(),
+                    call_stack: CallStack::new(self.call_stack.max_depth()),
+                    state: CoreState::Ready,
+                });
+                let pointer = self
+                    .resolve_argument_mut(&args[0])?
+                    .ok_or_else(|| String::from("Invalid argument"))?;
+                *pointer = id;
+            }
+            Command::Join => {
+                let Argument::Register(reg) = args[0] else {
+                    return Err("join needs the core in a register".into());
+                };
+                let id = self.registers.data[reg as usize];
+                match self.cores.state(id) {
+                    None => return Err(format!("unknown core {id}").into()),
+                    Some(CoreState::Finished(result)) => self.registers.data[reg as usize] = result,
+                    Some(_) => {
+                        // run join again once the core has finished
+                        self.cores.set_state(CoreState::Joining(id));
+                        increase_program_counter = false;
+                    }
+                }
+            }
+            Command::Alloc => {
+                let label = args[0].resolve_label_or_error()?;
+                if let Ok(handle) = self.allocated_handle(label) {
+                    return Err(RuntimeError::Fault(
+                        Fault::InvalidHeapAccess,
+                        format!(
+                            ":{label} is still allocated as heap entry {handle}, free it first"
+                        ),
+                    ));
+                }
+                // rebinding a data or code label would leave its users pointing elsewhere
+                if self.label_references.contains_key(&label) {
+                    return Err(RuntimeError::Fault(
+                        Fault::InvalidHeapAccess,
+                        format!(":{label} is already bound to data or code"),
+                    ));
+                }
+                let data = vec![0; self.resolve_size(&args[1])?];
+                let handle = match self.allocations.take_free_slot() {
+                    Some(handle) => {
+                        self.heap[handle as usize] = data;
+                        handle
+                    }
+                    None => {
+                        self.heap.push(data);
+                        (self.heap.len() - 1) as Integer
+                    }
+                };
+                self.label_references.insert(label, handle);
+                self.allocations.allocated(handle, label);
+            }
+            Command::Free => {
+                let label = args[0].resolve_label_or_error()?;
+                let handle = self.allocated_handle(label)?;
+                self.heap[handle as usize] = Vec::new();
+                self.label_references.remove(&label);
+                self.allocations.freed(handle);
+            }
+            Command::Realloc => {
+                let label = args[0].resolve_label_or_error()?;
+                let handle = self.allocated_handle(label)?;
+                let size = self.resolve_size(&args[1])?;
+                self.heap[handle as usize].resize(size, 0);
+                self.allocations.reallocated();
+            }
+            Command::Interrupt => {
+                let vector = self.resolve_argument_or_error(&args[0])?;
+                if !self.enter_interrupt(vector, self.program_counter + 1, None) {
+                    return Err(format!("unhandled interrupt {vector}").into());
+                }
+            }
+            Command::InterruptReturn => {
+                let frame = self.interrupts.leave()?;
+                if frame.call_depth != self.call_stack.depth() {
+                    return Err("iret with calls still active in the handler".into());
+                }
+                self.flags = frame.flags;
+                if let Some(registers) = frame.registers {
+                    self.registers = registers;
+                }
+                self.program_counter = frame.return_address;
+                increase_program_counter = false;
+            }
+            Command::EnableInterrupts => self.interrupts.set_enabled(true),
+            Command::DisableInterrupts => self.interrupts.set_enabled(false),
+            Command::SetInterruptVector => {
+                let vector = self.resolve_argument_or_error(&args[0])?;
+                let label = args[1].resolve_label_or_error()?;
+                self.interrupts.set_vector(vector, label);
+            }
+            Command::Flush => self.devices.flush()?,
+            Command::Halt if self.cores.current() == MAIN_CORE => {
+                self.stopped = Some(RunOutcome::Halted)
+            }
+            Command::Halt => self
+                .cores
+                .set_state(CoreState::Finished(self.registers.data[0])),
+            Command::Exit => {
+                let code = self.resolve_argument_or_error(&args[0])?;
+                if code > u8::MAX as Integer {
+                    return Err(
+                        format!("exit code {code} is out of range, it must be 0 to 255").into(),
+                    );
+                }
+                self.stopped = Some(RunOutcome::Exited(code));
+            }
+            Command::Length => {
+                let label = args[1].resolve_label_or_error()?;
+                let length = self.heap_entry(label)?.len() as Integer;
+                let pointer = self
+                    .resolve_argument_mut(&args[0])?
+                    .ok_or_else(|| String::from("Invalid argument"))?;
+                *pointer = length;
             }
         }
 
@@ -323,53 +761,274 @@ impl Runtime {
         self.registers.data[0]
     }
 
+    pub fn cycles(&self) -> Integer {
+        self.cycles
+    }
+
+    /// Number of instructions executed so far.
+    pub fn instructions(&self) -> Integer {
+        self.instructions
+    }
+
+    pub fn cores(&self) -> &Cores {
+        &self.cores
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn heap_stats(&self) -> &HeapStats {
+        self.allocations.stats()
+    }
+
+    /// Heap entries created with `alloc` that are still live.
+    pub fn leaks(&self) -> Vec<Leak> {
+        self.allocations
+            .live()
+            .map(|(handle, label)| Leak {
+                handle,
+                label,
+                length: self.heap[handle as usize].len(),
+            })
+            .collect()
+    }
+
+    fn load(&mut self, address: Integer) -> Result<Integer, RuntimeError> {
+        match self.devices.read(address) {
+            Some(result) => result,
+            None => self.memory.read(address),
+        }
+        .map_err(|e| RuntimeError::Fault(Fault::InvalidMemoryAccess, e))
+    }
+
+    fn store(&mut self, address: Integer, value: Integer) -> Result<(), RuntimeError> {
+        match self.devices.write(address, value) {
+            Some(result) => result,
+            None => self.memory.write(address, value),
+        }
+        .map_err(|e| RuntimeError::Fault(Fault::InvalidMemoryAccess, e))
+    }
+
+    /// Switches to the next core when the running one used up its turn, stopped or is waiting.
+    ///
+    /// Interrupt handlers are not preempted, the interrupt state is shared by all cores.
+    fn schedule(&mut self) -> Result<(), Error> {
+        let preemptible = self.interrupts.depth() == 0;
+        let Some(next) = self.cores.next(preemptible)? else {
+            return Ok(());
+        };
+
+        let current = Core {
+            registers: std::mem::take(&mut self.registers),
+            flags: std::mem::take(&mut self.flags),
+            program_counter: self.program_counter,
+            stack: std::mem::take(&mut self.stack),
+            call_stack: std::mem::take(&mut self.call_stack),
+            state: CoreState::Ready,
+        };
+        let core = self.cores.switch(next, current);
+        self.registers = core.registers;
+        self.flags = core.flags;
+        self.program_counter = core.program_counter;
+        self.stack = core.stack;
+        self.call_stack = core.call_stack;
+        Ok(())
+    }
+
+    /// Delivers the timer interrupt before the next instruction, saving the full context.
+    fn poll_timer(&mut self) {
+        let Some(timer) = self.timer.as_mut() else {
+            return;
+        };
+        if !self.interrupts.enabled() || !timer.pending(self.instructions) {
+            return;
+        }
+        timer.acknowledge(self.instructions);
+        let vector = timer.vector();
+
+        let registers = Some(self.registers.clone());
+        self.enter_interrupt(vector, self.program_counter, registers);
+    }
+
+    /// Jumps to the handler of `vector`, returns false when there is none.
+    ///
+    /// `iret` continues at `return_address` and restores `registers` when they are saved.
+    fn enter_interrupt(
+        &mut self,
+        vector: Integer,
+        return_address: Integer,
+        registers: Option<Registers>,
+    ) -> bool {
+        let Some(line) = self
+            .interrupts
+            .handler(vector)
+            .and_then(|label| self.label_references.get(&label).copied())
+        else {
+            return false;
+        };
+
+        self.interrupts.enter(InterruptFrame {
+            vector,
+            return_address,
+            flags: self.flags.clone(),
+            call_depth: self.call_stack.depth(),
+            enabled: false,
+            registers,
+        });
+        self.program_counter = line;
+        true
+    }
+
+    fn with_backtrace(&self, error: Error) -> Error {
+        if self.call_stack.depth() == 0 {
+            return error;
+        }
+        format!(
+            "{error}\n{}",
+            self.call_stack.backtrace(self.program_counter)
+        )
+    }
+
     fn resolve_argument(&self, argument: &Argument) -> Option<Integer> {
         match argument {
             Argument::None => None,
             Argument::Raw(data) => Some(*data),
             Argument::Register(reg_id) => self.registers.data.get(*reg_id as usize).copied(),
-            Argument::HeapRef(ref_id) => Some(*self.label_references.get(&ref_id).unwrap()),
+            Argument::HeapRef(ref_id) => Some(*self.label_references.get(ref_id).unwrap()),
             Argument::RawLabel(label) => self.label_references.get(label).copied(),
-            Argument::HeapDeref(label, offset) => {
-                let ref_id = self.label_references.get(label).copied();
-                ref_id
-                    .map(|x| {
-                        self.heap
-                            .get(x as usize)
-                            .map(|y| y.get(*offset as usize).copied())
-                    })
-                    .flatten()
-                    .flatten()
-            }
-            Argument::Literal(_) => todo!(),
+            Argument::HeapDeref(_, _) | Argument::HeapIndex(_, _) => self
+                .heap_index(argument)
+                .and_then(|(label, index)| self.read_heap(label, index).ok()),
+            Argument::Literal(_) => None,
+            Argument::CycleCounter => Some(self.cycles),
+            // memory is only accessed through ld and st
+            Argument::Memory(_, _) => None,
         }
     }
 
-    fn resolve_argument_mut(&mut self, argument: &Argument) -> Option<&mut Integer> {
-        match argument {
+    /// The place `argument` writes to, `None` when it cannot be written, heap operands
+    /// fault when they are out of bounds.
+    fn resolve_argument_mut(
+        &mut self,
+        argument: &Argument,
+    ) -> Result<Option<&mut Integer>, RuntimeError> {
+        let pointer = match argument {
             Argument::None => None,
             Argument::Raw(_data) => None,
             Argument::Register(reg_id) => self.registers.data.get_mut(*reg_id as usize),
-            Argument::HeapRef(ref_id) => self.label_references.get_mut(&ref_id),
+            Argument::HeapRef(ref_id) => self.label_references.get_mut(ref_id),
             Argument::RawLabel(label) => self.label_references.get_mut(label),
-            Argument::HeapDeref(label, offset) => {
-                let ref_id = self.label_references.get(label).copied();
-                ref_id
-                    .map(|x| {
-                        self.heap
-                            .get_mut(x as usize)
-                            .map(|y| y.get_mut(*offset as usize))
-                    })
-                    .flatten()
-                    .flatten()
+            Argument::HeapDeref(_, _) | Argument::HeapIndex(_, _) => {
+                let Some((label, index)) = self.heap_index(argument) else {
+                    return Ok(None);
+                };
+                self.read_heap(label, index)?;
+                self.heap_entry_mut(label)?.get_mut(index)
             }
-            Argument::Literal(_) => todo!(),
-        }
+            Argument::Literal(_) => None,
+            // the cycle counter is read-only
+            Argument::CycleCounter => None,
+            Argument::Memory(_, _) => None,
+        };
+        Ok(pointer)
     }
 
-    pub fn resolve_argument_or_error(&self, argument: &Argument) -> Result<Integer, Error> {
+    pub fn resolve_argument_or_error(&self, argument: &Argument) -> Result<Integer, RuntimeError> {
+        if let Some((label, index)) = self.heap_index(argument) {
+            return self.read_heap(label, index);
+        }
         self.resolve_argument(argument)
-            .ok_or_else(|| String::from("no valid argument"))
+            .ok_or_else(|| RuntimeError::from("no valid argument"))
+    }
+
+    /// Label and element index addressed by a heap operand.
+    fn heap_index(&self, argument: &Argument) -> Option<(Integer, usize)> {
+        match argument {
+            Argument::HeapDeref(label, offset) => Some((*label, *offset)),
+            Argument::HeapIndex(label, reg) => {
+                let index = self.registers.data[*reg as usize];
+                Some((*label, usize::try_from(index).unwrap_or(usize::MAX)))
+            }
+            _ => None,
+        }
+    }
+
+    fn heap_entry(&self, label: Integer) -> Result<&Vec<Integer>, RuntimeError> {
+        self.label_references
+            .get(&label)
+            .and_then(|heap_id| self.heap.get(*heap_id as usize))
+            .ok_or_else(|| {
+                RuntimeError::Fault(
+                    Fault::InvalidHeapAccess,
+                    format!("unknown heap label :{label}"),
+                )
+            })
+    }
+
+    fn heap_entry_mut(&mut self, label: Integer) -> Result<&mut Vec<Integer>, RuntimeError> {
+        match self.label_references.get(&label) {
+            Some(heap_id) if (*heap_id as usize) < self.heap.len() => {
+                Ok(&mut self.heap[*heap_id as usize])
+            }
+            _ => Err(RuntimeError::Fault(
+                Fault::InvalidHeapAccess,
+                format!("unknown heap label :{label}"),
+            )),
+        }
+    }
+
+    fn allocated_handle(&self, label: Integer) -> Result<Integer, Error> {
+        match self.label_references.get(&label) {
+            Some(handle) if self.allocations.is_live(*handle) => Ok(*handle),
+            _ => Err(format!(":{label} is not an allocated heap entry")),
+        }
+    }
+
+    /// A heap entry size, sizes over `MAX_HEAP_ENTRY_SIZE` raise a heap fault.
+    fn resolve_size(&self, argument: &Argument) -> Result<usize, RuntimeError> {
+        let size = self.resolve_argument_or_error(argument)?;
+        usize::try_from(size)
+            .ok()
+            .filter(|size| *size <= MAX_HEAP_ENTRY_SIZE)
+            .ok_or_else(|| {
+                RuntimeError::Fault(
+                    Fault::InvalidHeapAccess,
+                    format!(
+                        "heap entry size {size} is larger than the maximum of {MAX_HEAP_ENTRY_SIZE}"
+                    ),
+                )
+            })
+    }
+
+    fn read_heap(&self, label: Integer, index: usize) -> Result<Integer, RuntimeError> {
+        let data = self.heap_entry(label)?;
+        data.get(index).copied().ok_or_else(|| {
+            RuntimeError::Fault(
+                Fault::InvalidHeapAccess,
+                format!(
+                    "heap access out of bounds: index {index} of :{label} with length {}",
+                    data.len()
+                ),
+            )
+        })
+    }
+
+    fn resolve_address(&self, argument: &Argument) -> Result<Integer, Error> {
+        let Argument::Memory(base, offset) = argument else {
+            return Err(String::from("expected a memory operand"));
+        };
+        let base = self.registers.data[*base as usize];
+        let offset = match offset {
+            Offset::Raw(offset) => *offset,
+            Offset::Register(reg) => self.registers.data[*reg as usize],
+        };
+        base.checked_add(offset)
+            .ok_or_else(|| String::from("memory address overflow"))
     }
 
     fn brancher(&mut self, args: &[Argument; 2]) -> Result<(), Error> {
@@ -382,7 +1041,7 @@ impl Runtime {
         Ok(())
     }
 
-    fn calculate(&mut self, command: &Command, args: &[Argument; 2]) -> Result<(), Error> {
+    fn calculate(&mut self, command: &Command, args: &[Argument; 2]) -> Result<(), RuntimeError> {
         let function: fn(u64, u64) -> (u64, bool) = match command {
             Command::Add => Integer::overflowing_add,
             Command::Subtract => Integer::overflowing_sub,
@@ -391,15 +1050,20 @@ impl Runtime {
             Command::Modulo => Integer::overflowing_rem,
             // Command::Shiftleft => Integer::overflowing_shl,
             // Command::Shiftright => Integer::overflowing_shr,
-            _ => return Err("Invalid calculate command".to_string()),
+            _ => return Err("Invalid calculate command".into()),
         };
 
-        let (out, overflow) = function(
-            self.resolve_argument_or_error(&args[0])?,
-            self.resolve_argument_or_error(&args[1])?,
-        );
+        let value_a = self.resolve_argument_or_error(&args[0])?;
+        let value_b = self.resolve_argument_or_error(&args[1])?;
+        if value_b == 0 && matches!(command, Command::Divide | Command::Modulo) {
+            return Err(RuntimeError::Fault(
+                Fault::DivideByZero,
+                String::from("divide by zero"),
+            ));
+        }
+        let (out, overflow) = function(value_a, value_b);
         // self.registers.data[0] = out;
-        if let Some(pointer) = self.resolve_argument_mut(&args[0]) {
+        if let Some(pointer) = self.resolve_argument_mut(&args[0])? {
             *pointer = out;
         }
         self.flags.overflow = overflow;
@@ -422,6 +1086,7 @@ impl Runtime {
 mod tests {
     use super::*;
     use maplit::btreemap;
+    use std::cell::Cell;
 
     #[test]
     fn simple_add_test() {
@@ -543,76 +1208,1199 @@ mod tests {
     }
 
     #[test]
-    fn string_literal() {
-        let data_str = 12529907765057034586;
-        let data_str2 = 12529904465057034586;
+    fn run_outcome_test() {
+        let program = btreemap! {
+            0 => (Command::Move, [Argument::Register(0), Argument::Raw(1)]),
+            1 => (Command::Compare, [Argument::Register(1), Argument::Raw(1)]),
+            2 => (Command::BranchEqual, [Argument::RawLabel(8411), Argument::None]),
+            3 => (Command::Halt, [Argument::None, Argument::None]),
+            4 => (Command::Label, [Argument::RawLabel(8411), Argument::None]),
+            5 => (Command::Exit, [Argument::Raw(3), Argument::None]),
+            6 => (Command::Move, [Argument::Register(0), Argument::Raw(2)]),
+        };
 
-        let mut rt = Runtime::new(maplit::btreemap! {
-            1 => (Command::LabelledData(data_str), [Argument::Literal("Hallo\0".chars().map(|x| x as Integer).collect()), Argument::None]),
-            2 => (Command::LabelledData(data_str2), [Argument::Literal("Test\0".chars().map(|x| x as Integer).collect()), Argument::None]),
-            3 => (Command::Move, [Argument::Register(1), Argument::RawLabel(data_str)]),
-            4 => (Command::Move, [Argument::Register(0), Argument::RawLabel(data_str2)]),
-            5 => (Command::Move, [Argument::Register(2), Argument::HeapDeref(data_str, 0)]),
-            6 => (Command::Move, [Argument::Register(3), Argument::HeapDeref(data_str, 1)]),
-        });
+        let mut rt = Runtime::new(program.clone());
+        assert_eq!(RunOutcome::Halted, rt.run().unwrap());
+        assert_eq!(1, rt.output());
 
-        rt.run().unwrap();
+        let mut rt = Runtime::new(program.clone());
+        rt.registers.data[1] = 1;
+        assert_eq!(RunOutcome::Exited(3), rt.run().unwrap());
+        assert_eq!(1, rt.output());
 
-        assert_eq!(1, rt.registers.data[0]);
-        assert_eq!(0, rt.registers.data[1]);
-        assert_eq!(b'H' as Integer, rt.registers.data[2]);
-        assert_eq!(b'a' as Integer, rt.registers.data[3]);
+        let mut rt = Runtime::new(program).with_instruction_limit(2);
+        assert_eq!(RunOutcome::LimitReached, rt.run().unwrap());
+        assert_eq!(2, rt.instructions());
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(0), Argument::Raw(1)]),
+        });
+        assert_eq!(RunOutcome::FellOffEnd, rt.run().unwrap());
     }
 
     #[test]
-    fn string_append() {
-        let data_str = 12529907765057034586;
+    fn exit_from_call_test() {
+        let function = 8411;
 
-        let mut rt = Runtime::new(maplit::btreemap! {
-            1 => (Command::LabelledData(data_str), [Argument::Literal(Vec::new()), Argument::None]),
-            2 => (Command::Move, [Argument::Register(1), Argument::Raw(1)]),
-            3 => (Command::Move, [Argument::Register(2), Argument::Raw(2)]),
-            4 => (Command::Move, [Argument::Register(3), Argument::Raw(3)]),
-            5 => (Command::Move, [Argument::HeapDeref(data_str, 0), Argument::Register(1)]),
-            6 => (Command::Move, [Argument::HeapDeref(data_str, 1), Argument::Register(2)]),
-            7 => (Command::Move, [Argument::HeapDeref(data_str, 2), Argument::Register(3)]),
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Call, [Argument::RawLabel(function), Argument::None]),
+            1 => (Command::Move, [Argument::Register(0), Argument::Raw(1)]),
+            2 => (Command::Label, [Argument::RawLabel(function), Argument::None]),
+            3 => (Command::Exit, [Argument::Raw(7), Argument::None]),
+            4 => (Command::Return, [Argument::None, Argument::None]),
         });
 
-        rt.run().unwrap();
+        assert_eq!(RunOutcome::Exited(7), rt.run().unwrap());
+        assert_eq!(0, rt.output());
 
-        let heap_id = rt.label_references[&data_str] as usize;
-        assert_eq!(rt.heap[heap_id], vec![1, 2, 3]);
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Exit, [Argument::Raw(300), Argument::None]),
+        });
+        assert!(rt
+            .run()
+            .unwrap_err()
+            .starts_with("exit code 300 is out of range, it must be 0 to 255"));
     }
 
     #[test]
-    fn external_function_call_print() {
-        let print_label = hash_label("print");
-        let data_str = 12529907765057034586;
+    fn divide_by_zero_handler_test() {
+        let handler = 8411;
 
-        let mut rt = Runtime::new(maplit::btreemap! {
-            1 => (Command::LabelledData(data_str), [Argument::Literal("Hallo".chars().map(|x| x as Integer).collect()), Argument::None]),
-            2 => (Command::Push, [Argument::RawLabel(data_str), Argument::None]),
-            3 => (Command::Function, [Argument::RawLabel(print_label), Argument::None])
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::SetInterruptVector, [Argument::Raw(Fault::DivideByZero.vector()), Argument::RawLabel(handler)]),
+            1 => (Command::Move, [Argument::Register(0), Argument::Raw(10)]),
+            2 => (Command::Compare, [Argument::Register(0), Argument::Raw(10)]),
+            3 => (Command::Divide, [Argument::Register(0), Argument::Register(1)]),
+            4 => (Command::Add, [Argument::Register(0), Argument::Raw(1)]),
+            5 => (Command::BranchEqual, [Argument::RawLabel(18427), Argument::None]),
+            6 => (Command::Halt, [Argument::None, Argument::None]),
+            7 => (Command::Label, [Argument::RawLabel(handler), Argument::None]),
+            8 => (Command::Move, [Argument::Register(2), Argument::Raw(99)]),
+            9 => (Command::Compare, [Argument::Register(2), Argument::Raw(0)]),
+            10 => (Command::InterruptReturn, [Argument::None, Argument::None]),
+            11 => (Command::Label, [Argument::RawLabel(18427), Argument::None]),
+            12 => (Command::Move, [Argument::Register(3), Argument::Raw(1)]),
         });
 
         rt.run().unwrap();
+
+        // the faulting divide is skipped and the flags are restored by iret
+        assert_eq!(11, rt.output());
+        assert_eq!(99, rt.registers.data[2]);
+        assert_eq!(1, rt.registers.data[3]);
+        assert_eq!(0, rt.interrupts.depth());
     }
 
     #[test]
-    fn external_function_call_getrandom() {
-        let getrandom_label = hash_label("getrandom");
+    fn unhandled_fault_test() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Divide, [Argument::Register(0), Argument::Register(1)]),
+        });
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::DivideByZero,
+                String::from("divide by zero")
+            )),
+            rt.run()
+        );
 
-        let mut rt = Runtime::new(maplit::btreemap! {
-            1 => (Command::Function, [Argument::RawLabel(getrandom_label), Argument::None]),
-            2 => (Command::Pop, [Argument::Register(0), Argument::None]),
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Pop, [Argument::Register(0), Argument::None]),
         });
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::StackUnderflow,
+                String::from("stack underflow")
+            )),
+            rt.run()
+        );
+    }
 
-        rt.run().unwrap();
+    #[test]
+    fn apply_command_reports_the_fault() {
+        let mut rt = Runtime::new(btreemap! {});
+        let divide = [Argument::Register(0), Argument::Register(1)];
+        assert_eq!(
+            Err(RuntimeError::Fault(
+                Fault::DivideByZero,
+                String::from("divide by zero")
+            )),
+            rt.apply_command(&Command::Divide, &divide)
+        );
+
+        let load = [Argument::Raw(1), Argument::Raw(0)];
+        assert!(matches!(
+            rt.apply_command(&Command::Load, &load),
+            Err(RuntimeError::Error(_))
+        ));
+    }
+
+    #[test]
+    fn software_interrupt_test() {
+        let handler = 8411;
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::SetInterruptVector, [Argument::Raw(32), Argument::RawLabel(handler)]),
+            1 => (Command::Interrupt, [Argument::Raw(32), Argument::None]),
+            2 => (Command::Interrupt, [Argument::Raw(32), Argument::None]),
+            3 => (Command::Halt, [Argument::None, Argument::None]),
+            4 => (Command::Label, [Argument::RawLabel(handler), Argument::None]),
+            5 => (Command::Add, [Argument::Register(0), Argument::Raw(1)]),
+            6 => (Command::InterruptReturn, [Argument::None, Argument::None]),
+        });
 
-        let output = rt.output();
+        assert_eq!(RunOutcome::Halted, rt.run().unwrap());
+        assert_eq!(2, rt.output());
 
-        assert_ne!(0, output);
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Interrupt, [Argument::Raw(33), Argument::None]),
+        });
+        assert_eq!("unhandled interrupt 33", rt.run().unwrap_err());
+    }
+
+    #[test]
+    fn heap_fault_handler_test() {
+        let handler = 8411;
+        let data_str = 12529907765057034586;
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::SetInterruptVector, [Argument::Raw(Fault::InvalidHeapAccess.vector()), Argument::RawLabel(handler)]),
+            1 => (Command::LabelledData(data_str), [Argument::Literal(vec![1]), Argument::None]),
+            2 => (Command::Move, [Argument::Register(0), Argument::HeapDeref(data_str, 5)]),
+            3 => (Command::Halt, [Argument::None, Argument::None]),
+            4 => (Command::Label, [Argument::RawLabel(handler), Argument::None]),
+            5 => (Command::Move, [Argument::Register(0), Argument::Raw(404)]),
+            6 => (Command::InterruptReturn, [Argument::None, Argument::None]),
+        });
+
+        assert_eq!(RunOutcome::Halted, rt.run().unwrap());
+        assert_eq!(404, rt.output());
+    }
+
+    #[test]
+    fn timer_interrupt_test() {
+        let handler = 8411;
+        let loop_label = 18427;
+
+        let program = btreemap! {
+            0 => (Command::SetInterruptVector, [Argument::Raw(TIMER_VECTOR), Argument::RawLabel(handler)]),
+            1 => (Command::EnableInterrupts, [Argument::None, Argument::None]),
+            2 => (Command::Label, [Argument::RawLabel(loop_label), Argument::None]),
+            3 => (Command::Add, [Argument::Register(0), Argument::Raw(1)]),
+            4 => (Command::Compare, [Argument::Register(0), Argument::Raw(100)]),
+            5 => (Command::BranchLesser, [Argument::RawLabel(loop_label), Argument::None]),
+            6 => (Command::Halt, [Argument::None, Argument::None]),
+            7 => (Command::Label, [Argument::RawLabel(handler), Argument::None]),
+            8 => (Command::Noop, [Argument::None, Argument::None]),
+            9 => (Command::Load, [Argument::Register(0), Argument::Memory(1, Offset::Raw(0))]),
+            10 => (Command::Add, [Argument::Register(0), Argument::Raw(1)]),
+            11 => (Command::Store, [Argument::Memory(1, Offset::Raw(0)), Argument::Register(0)]),
+            12 => (Command::Move, [Argument::Register(0), Argument::Raw(1000)]),
+            13 => (Command::InterruptReturn, [Argument::None, Argument::None]),
+        };
+
+        let mut rt = Runtime::new(program.clone()).with_timer(Timer::new(20));
+        assert_eq!(RunOutcome::Halted, rt.run().unwrap());
+
+        // the handler clobbers r0, but the interrupted loop does not notice
+        assert_eq!(100, rt.output());
+        let fired = rt.memory().read(0).unwrap();
+        assert!(fired > 1);
+
+        // the same program is interrupted at the same points every run
+        let mut again = Runtime::new(program.clone()).with_timer(Timer::new(20));
+        again.run().unwrap();
+        assert_eq!(fired, again.memory().read(0).unwrap());
+        assert_eq!(rt.instructions(), again.instructions());
+
+        // without ei the timer never fires
+        let mut program = program;
+        program.insert(
+            1,
+            (Command::DisableInterrupts, [Argument::None, Argument::None]),
+        );
+        let mut rt = Runtime::new(program).with_timer(Timer::new(20));
+        rt.run().unwrap();
+        assert_eq!(0, rt.memory().read(0).unwrap());
+    }
+
+    fn counter_program(increment: [(Command, [Argument; 2]); 3]) -> Program {
+        let worker = 8411;
+        let loop_label = 18427;
+        let [load, add, store] = increment;
+
+        btreemap! {
+            0 => (Command::Move, [Argument::Register(2), Argument::Raw(0)]),
+            1 => (Command::Spawn, [Argument::Register(5), Argument::RawLabel(worker)]),
+            2 => (Command::Spawn, [Argument::Register(6), Argument::RawLabel(worker)]),
+            3 => (Command::Join, [Argument::Register(5), Argument::None]),
+            4 => (Command::Join, [Argument::Register(6), Argument::None]),
+            5 => (Command::Load, [Argument::Register(0), Argument::Memory(2, Offset::Raw(0))]),
+            6 => (Command::Halt, [Argument::None, Argument::None]),
+            7 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+            8 => (Command::Move, [Argument::Register(1), Argument::Raw(0)]),
+            9 => (Command::Label, [Argument::RawLabel(loop_label), Argument::None]),
+            10 => load,
+            11 => add,
+            12 => store,
+            13 => (Command::Add, [Argument::Register(1), Argument::Raw(1)]),
+            14 => (Command::Compare, [Argument::Register(1), Argument::Raw(100)]),
+            15 => (Command::BranchLesser, [Argument::RawLabel(loop_label), Argument::None]),
+            16 => (Command::Halt, [Argument::None, Argument::None]),
+        }
+    }
+
+    #[test]
+    fn racing_cores_are_reproducible() {
+        let program = counter_program([
+            (
+                Command::Load,
+                [Argument::Register(3), Argument::Memory(2, Offset::Raw(0))],
+            ),
+            (Command::Add, [Argument::Register(3), Argument::Raw(1)]),
+            (
+                Command::Store,
+                [Argument::Memory(2, Offset::Raw(0)), Argument::Register(3)],
+            ),
+        ]);
+        let run = |seed| {
+            let mut rt = Runtime::new(program.clone()).with_scheduler(Scheduler::seeded(5, seed));
+            assert_eq!(RunOutcome::Halted, rt.run().unwrap());
+            assert_eq!(3, rt.cores().count());
+            rt.output()
+        };
+
+        let lost_updates = run(7);
+        assert!(lost_updates < 200);
+        assert_eq!(lost_updates, run(7));
+
+        // one core at a time never loses an update
+        let mut rt = Runtime::new(program).with_scheduler(Scheduler::round_robin(10_000));
+        rt.run().unwrap();
+        assert_eq!(200, rt.output());
+    }
+
+    #[test]
+    fn fetch_add_is_atomic() {
+        let program = counter_program([
+            (Command::Move, [Argument::Register(3), Argument::Raw(1)]),
+            (
+                Command::FetchAdd,
+                [Argument::Memory(2, Offset::Raw(0)), Argument::Register(3)],
+            ),
+            (Command::Noop, [Argument::None, Argument::None]),
+        ]);
+
+        for seed in 0..5 {
+            let mut rt = Runtime::new(program.clone()).with_scheduler(Scheduler::seeded(5, seed));
+            rt.run().unwrap();
+            assert_eq!(200, rt.output());
+        }
+    }
+
+    #[test]
+    fn compare_and_swap_test() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(5)]),
+            1 => (Command::Store, [Argument::Memory(1, Offset::Raw(0)), Argument::Raw(3)]),
+            2 => (Command::CompareAndSwap, [Argument::Memory(1, Offset::Raw(0)), Argument::Raw(9)]),
+            3 => (Command::Move, [Argument::Register(2), Argument::Register(0)]),
+            4 => (Command::CompareAndSwap, [Argument::Memory(1, Offset::Raw(0)), Argument::Raw(9)]),
+            5 => (Command::Load, [Argument::Register(3), Argument::Memory(1, Offset::Raw(0))]),
+        });
+        rt.run().unwrap();
+
+        // the first swap fails and loads the current value, the second one succeeds
+        assert_eq!(3, rt.registers.data[2]);
+        assert_eq!(9, rt.registers.data[3]);
+        assert!(rt.flags.equal);
+    }
+
+    #[test]
+    fn join_returns_result_and_detects_deadlock() {
+        let worker = 8411;
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Spawn, [Argument::Register(1), Argument::RawLabel(worker)]),
+            1 => (Command::Join, [Argument::Register(1), Argument::None]),
+            2 => (Command::Move, [Argument::Register(0), Argument::Register(1)]),
+            3 => (Command::Halt, [Argument::None, Argument::None]),
+            4 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+            5 => (Command::Move, [Argument::Register(0), Argument::Raw(42)]),
+        });
+        assert_eq!(RunOutcome::Halted, rt.run().unwrap());
+        assert_eq!(42, rt.output());
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Spawn, [Argument::Register(1), Argument::RawLabel(worker)]),
+            1 => (Command::Join, [Argument::Register(1), Argument::None]),
+            2 => (Command::Halt, [Argument::None, Argument::None]),
+            3 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+            4 => (Command::Join, [Argument::Register(2), Argument::None]),
+        });
+        assert_eq!(
+            "deadlock: every core is waiting in join",
+            rt.run().unwrap_err()
+        );
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Spawn, [Argument::Raw(1), Argument::RawLabel(worker)]),
+            1 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+        });
+        assert!(rt.run().unwrap_err().starts_with("Invalid argument"));
+        assert_eq!(1, rt.cores().count());
+    }
+
+    #[test]
+    fn channels_between_runtimes() {
+        let send = hash_label("send");
+        let send_entry = hash_label("send_entry");
+        let recv = hash_label("recv");
+        let recv_entry = hash_label("recv_entry");
+        let message = 12529907765057034586;
+
+        let channels = Channels::default();
+        let producer = Runtime::new(btreemap! {
+            0 => (Command::LabelledData(message), [Argument::Literal(vec![104, 105]), Argument::None]),
+            1 => (Command::Push, [Argument::Raw(1), Argument::None]),
+            2 => (Command::Push, [Argument::Raw(40), Argument::None]),
+            3 => (Command::Function, [Argument::RawLabel(send), Argument::None]),
+            4 => (Command::Push, [Argument::Raw(2), Argument::None]),
+            5 => (Command::Push, [Argument::RawLabel(message), Argument::None]),
+            6 => (Command::Function, [Argument::RawLabel(send_entry), Argument::None]),
+        })
+        .with_channels(channels.clone());
+        let consumer = Runtime::new(btreemap! {
+            0 => (Command::LabelledData(message), [Argument::Literal(vec![]), Argument::None]),
+            1 => (Command::Push, [Argument::Raw(1), Argument::None]),
+            2 => (Command::Function, [Argument::RawLabel(recv), Argument::None]),
+            3 => (Command::Pop, [Argument::Register(0), Argument::None]),
+            4 => (Command::Add, [Argument::Register(0), Argument::Raw(2)]),
+            5 => (Command::Push, [Argument::RawLabel(message), Argument::None]),
+            6 => (Command::Push, [Argument::Raw(2), Argument::None]),
+            7 => (Command::Function, [Argument::RawLabel(recv_entry), Argument::None]),
+        })
+        .with_channels(channels);
+
+        // the consumer goes first and has to wait for the producer
+        let mut runtimes = [consumer, producer];
+        let outcomes = run_together(&mut runtimes, 1).unwrap();
+
+        assert_eq!(vec![RunOutcome::FellOffEnd; 2], outcomes);
+        assert_eq!(42, runtimes[0].output());
+        assert_eq!(
+            "hi",
+            decode_heap_binary_to_string(&runtimes[0].heap[0]).unwrap()
+        );
+    }
+
+    #[test]
+    fn channel_deadlock() {
+        let recv = hash_label("recv");
+        let program = btreemap! {
+            0 => (Command::Push, [Argument::Raw(1), Argument::None]),
+            1 => (Command::Function, [Argument::RawLabel(recv), Argument::None]),
+        };
+
+        let mut rt = Runtime::new(program.clone());
+        assert_eq!(
+            "deadlock: waiting on a channel that nothing can send to",
+            rt.run().unwrap_err()
+        );
+
+        let channels = Channels::default();
+        let mut runtimes = [
+            Runtime::new(program.clone()).with_channels(channels.clone()),
+            Runtime::new(program).with_channels(channels),
+        ];
+        assert_eq!(
+            "deadlock: every running program is waiting on a channel: 0, 1",
+            run_together(&mut runtimes, 10).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn channel_deadlock_after_spawn() {
+        let worker = 8411;
+        let recv = hash_label("recv");
+
+        // the worker has finished, nothing is left that could send
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Spawn, [Argument::Register(1), Argument::RawLabel(worker)]),
+            1 => (Command::Join, [Argument::Register(1), Argument::None]),
+            2 => (Command::Push, [Argument::Raw(1), Argument::None]),
+            3 => (Command::Function, [Argument::RawLabel(recv), Argument::None]),
+            4 => (Command::Halt, [Argument::None, Argument::None]),
+            5 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+        })
+        .with_instruction_limit(1000);
+        assert_eq!(
+            "deadlock: waiting on a channel that nothing can send to",
+            rt.run().unwrap_err()
+        );
+
+        // both cores wait for the other one
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Spawn, [Argument::Register(1), Argument::RawLabel(worker)]),
+            1 => (Command::Push, [Argument::Raw(1), Argument::None]),
+            2 => (Command::Function, [Argument::RawLabel(recv), Argument::None]),
+            3 => (Command::Halt, [Argument::None, Argument::None]),
+            4 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+            5 => (Command::Push, [Argument::Raw(2), Argument::None]),
+            6 => (Command::Function, [Argument::RawLabel(recv), Argument::None]),
+        })
+        .with_instruction_limit(1000);
+        assert_eq!(
+            "deadlock: waiting on a channel that nothing can send to",
+            rt.run().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn blocked_core_yields() {
+        let worker = 8411;
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Spawn, [Argument::Register(1), Argument::RawLabel(worker)]),
+            1 => (Command::Push, [Argument::Raw(1), Argument::None]),
+            2 => (Command::Function, [Argument::RawLabel(hash_label("recv")), Argument::None]),
+            3 => (Command::Pop, [Argument::Register(0), Argument::None]),
+            4 => (Command::Halt, [Argument::None, Argument::None]),
+            5 => (Command::Label, [Argument::RawLabel(worker), Argument::None]),
+            6 => (Command::Push, [Argument::Raw(1), Argument::None]),
+            7 => (Command::Push, [Argument::Raw(42), Argument::None]),
+            8 => (Command::Function, [Argument::RawLabel(hash_label("send")), Argument::None]),
+        });
+
+        assert_eq!(RunOutcome::Halted, rt.run().unwrap());
+        assert_eq!(42, rt.output());
+        // the receive that had to wait is not counted
+        assert_eq!(9, rt.instructions());
+    }
+
+    #[test]
+    fn call_with_data_on_stack_test() {
+        let add_one = 8411;
+        let end = 18427;
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Push, [Argument::Raw(15), Argument::None]),
+            1 => (Command::Call, [Argument::RawLabel(add_one), Argument::None]),
+            2 => (Command::Pop, [Argument::Register(0), Argument::None]),
+            3 => (Command::Branch, [Argument::RawLabel(end), Argument::None]),
+            4 => (Command::Label, [Argument::RawLabel(add_one), Argument::None]),
+            5 => (Command::Pop, [Argument::Register(1), Argument::None]),
+            6 => (Command::Add, [Argument::Register(1), Argument::Raw(100)]),
+            7 => (Command::Push, [Argument::Register(1), Argument::None]),
+            8 => (Command::Return, [Argument::None, Argument::None]),
+            9 => (Command::Label, [Argument::RawLabel(end), Argument::None]),
+        });
+
+        rt.run().unwrap();
+
+        assert_eq!(115, rt.output());
+        assert_eq!(0, rt.call_stack().depth());
+    }
+
+    #[test]
+    fn return_without_call_test() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Return, [Argument::None, Argument::None]),
+        });
+
+        let Ok(RunOutcome::Trapped(Fault::StackUnderflow, error)) = rt.run() else {
+            panic!("expected a stack underflow");
+        };
+
+        assert!(error.starts_with("call stack underflow"));
+    }
+
+    #[test]
+    fn stack_overflow_test() {
+        let recurse = 8411;
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Label, [Argument::RawLabel(recurse), Argument::None]),
+            1 => (Command::Call, [Argument::RawLabel(recurse), Argument::None]),
+        })
+        .with_max_call_depth(3);
+
+        let Ok(RunOutcome::Trapped(Fault::StackOverflow, error)) = rt.run() else {
+            panic!("expected a stack overflow");
+        };
+
+        assert_eq!(
+            error,
+            "stack overflow: maximum call depth of 3 exceeded
+backtrace:
+    0: line 1 in :8411 (called from line 1)
+    1: line 1 in :8411 (called from line 1)
+    2: line 1 in :8411 (called from line 1)
+    3: line 1"
+        );
+    }
+
+    #[test]
+    fn load_store_test() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(100)]),
+            1 => (Command::Move, [Argument::Register(2), Argument::Raw(3)]),
+            2 => (Command::Move, [Argument::Register(3), Argument::Raw(42)]),
+            3 => (Command::Store, [Argument::Memory(1, Offset::Register(2)), Argument::Register(3)]),
+            4 => (Command::Store, [Argument::Memory(1, Offset::Raw(8)), Argument::Raw(7)]),
+            5 => (Command::Move, [Argument::Register(1), Argument::Raw(103)]),
+            6 => (Command::Load, [Argument::Register(0), Argument::Memory(1, Offset::Raw(0))]),
+            7 => (Command::Load, [Argument::Register(4), Argument::Memory(1, Offset::Raw(5))]),
+        });
+
+        rt.run().unwrap();
+
+        assert_eq!(42, rt.output());
+        assert_eq!(7, rt.registers.data[4]);
+        assert_eq!(42, rt.memory().read(103).unwrap());
+    }
+
+    #[test]
+    fn load_out_of_bounds_test() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(16)]),
+            1 => (Command::Load, [Argument::Register(0), Argument::Memory(1, Offset::Raw(0))]),
+        })
+        .with_memory_size(16);
+
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidMemoryAccess,
+                String::from("memory access out of bounds: address 16")
+            )),
+            rt.run()
+        );
+    }
+
+    #[test]
+    fn string_literal() {
+        let data_str = 12529907765057034586;
+        let data_str2 = 12529904465057034586;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            1 => (Command::LabelledData(data_str), [Argument::Literal("Hallo\0".chars().map(|x| x as Integer).collect()), Argument::None]),
+            2 => (Command::LabelledData(data_str2), [Argument::Literal("Test\0".chars().map(|x| x as Integer).collect()), Argument::None]),
+            3 => (Command::Move, [Argument::Register(1), Argument::RawLabel(data_str)]),
+            4 => (Command::Move, [Argument::Register(0), Argument::RawLabel(data_str2)]),
+            5 => (Command::Move, [Argument::Register(2), Argument::HeapDeref(data_str, 0)]),
+            6 => (Command::Move, [Argument::Register(3), Argument::HeapDeref(data_str, 1)]),
+        });
+
+        rt.run().unwrap();
+
+        assert_eq!(1, rt.registers.data[0]);
+        assert_eq!(0, rt.registers.data[1]);
+        assert_eq!(b'H' as Integer, rt.registers.data[2]);
+        assert_eq!(b'a' as Integer, rt.registers.data[3]);
+    }
+
+    #[test]
+    fn data_is_placed_before_it_runs() {
+        let data_str = 12529907765057034586;
+        let start = 2184574;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::Label, [Argument::RawLabel(start), Argument::None]),
+            1 => (Command::Move, [Argument::Register(0), Argument::HeapDeref(data_str, 1)]),
+            2 => (Command::LabelledData(data_str), [Argument::Literal(vec![7, 8]), Argument::None]),
+            3 => (Command::Add, [Argument::Register(1), Argument::Raw(1)]),
+            4 => (Command::Compare, [Argument::Register(1), Argument::Raw(2)]),
+            5 => (Command::BranchLesser, [Argument::RawLabel(start), Argument::None]),
+        });
+        rt.run().unwrap();
+
+        assert_eq!(8, rt.output());
+        assert_eq!(2, rt.registers.data[1]);
+        assert_eq!(vec![vec![7, 8]], rt.heap);
+    }
+
+    #[test]
+    fn string_append() {
+        let data_str = 12529907765057034586;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            1 => (Command::LabelledData(data_str), [Argument::Literal(Vec::new()), Argument::None]),
+            2 => (Command::Move, [Argument::Register(1), Argument::Raw(1)]),
+            3 => (Command::Move, [Argument::Register(2), Argument::Raw(2)]),
+            4 => (Command::Move, [Argument::Register(3), Argument::Raw(3)]),
+            5 => (Command::Move, [Argument::HeapDeref(data_str, 0), Argument::Register(1)]),
+            6 => (Command::Move, [Argument::HeapDeref(data_str, 1), Argument::Register(2)]),
+            7 => (Command::Move, [Argument::HeapDeref(data_str, 2), Argument::Register(3)]),
+        });
+
+        rt.run().unwrap();
+
+        let heap_id = rt.label_references[&data_str] as usize;
+        assert_eq!(rt.heap[heap_id], vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn heap_write_past_maximum_test() {
+        let data_str = 12529907765057034586;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::LabelledData(data_str), [Argument::Literal(vec![1]), Argument::None]),
+            1 => (Command::Move, [Argument::Register(1), Argument::Raw(Integer::MAX)]),
+            2 => (Command::Move, [Argument::HeapIndex(data_str, 1), Argument::Raw(1)]),
+        });
+
+        let Ok(RunOutcome::Trapped(Fault::InvalidHeapAccess, error)) = rt.run() else {
+            panic!("expected an invalid heap access");
+        };
+        assert!(error.starts_with("heap write out of bounds"), "{error}");
+        assert_eq!(vec![vec![1]], rt.heap);
+    }
+
+    #[test]
+    fn heap_index_loop_test() {
+        let data_str = 12529907765057034586;
+        let start = 1254;
+        let stop = 666;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::LabelledData(data_str), [Argument::Literal(vec![1, 2, 3, 4]), Argument::None]),
+            1 => (Command::Label, [Argument::RawLabel(start), Argument::None]),
+            2 => (Command::Compare, [Argument::Register(1), Argument::Raw(4)]),
+            3 => (Command::BranchGreaterEqual, [Argument::RawLabel(stop), Argument::None]),
+            4 => (Command::Add, [Argument::Register(0), Argument::HeapIndex(data_str, 1)]),
+            5 => (Command::Multiply, [Argument::HeapIndex(data_str, 1), Argument::Raw(10)]),
+            6 => (Command::Add, [Argument::Register(1), Argument::Raw(1)]),
+            7 => (Command::Branch, [Argument::RawLabel(start), Argument::None]),
+            8 => (Command::Label, [Argument::RawLabel(stop), Argument::None]),
+            9 => (Command::Move, [Argument::HeapIndex(data_str, 1), Argument::Raw(50)]),
+        });
+
+        rt.run().unwrap();
+
+        assert_eq!(10, rt.output());
+        let heap_id = rt.label_references[&data_str] as usize;
+        assert_eq!(rt.heap[heap_id], vec![10, 20, 30, 40, 50]);
+    }
+
+    #[test]
+    fn heap_index_out_of_bounds_test() {
+        let data_str = 12529907765057034586;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::LabelledData(data_str), [Argument::Literal(vec![1, 2, 3]), Argument::None]),
+            1 => (Command::Move, [Argument::Register(1), Argument::Raw(3)]),
+            2 => (Command::Move, [Argument::Register(0), Argument::HeapIndex(data_str, 1)]),
+        });
+
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidHeapAccess,
+                format!("heap access out of bounds: index 3 of :{data_str} with length 3")
+            )),
+            rt.run()
+        );
+    }
+
+    #[test]
+    fn pop_out_of_bounds_test() {
+        let data_str = 12529907765057034586;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::LabelledData(data_str), [Argument::Literal(vec![1, 2, 3]), Argument::None]),
+            1 => (Command::Move, [Argument::Register(1), Argument::Raw(3)]),
+            2 => (Command::Push, [Argument::Raw(7), Argument::None]),
+            3 => (Command::Pop, [Argument::HeapIndex(data_str, 1), Argument::None]),
+        });
+
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidHeapAccess,
+                format!("heap access out of bounds: index 3 of :{data_str} with length 3")
+            )),
+            rt.run()
+        );
+        assert_eq!(vec![vec![1, 2, 3]], rt.heap);
+    }
+
+    #[test]
+    fn alloc_free_test() {
+        let buffer = 8411;
+        let other = 8412;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(4)]),
+            1 => (Command::Move, [Argument::Register(1), Argument::Raw(3)]),
+            2 => (Command::Move, [Argument::HeapIndex(buffer, 1), Argument::Raw(42)]),
+            3 => (Command::Realloc, [Argument::RawLabel(buffer), Argument::Raw(8)]),
+            4 => (Command::Length, [Argument::Register(0), Argument::RawLabel(buffer)]),
+            5 => (Command::Move, [Argument::Register(2), Argument::HeapIndex(buffer, 1)]),
+            6 => (Command::Free, [Argument::RawLabel(buffer), Argument::None]),
+            7 => (Command::Alloc, [Argument::RawLabel(other), Argument::Raw(2)]),
+            8 => (Command::Move, [Argument::Register(3), Argument::RawLabel(other)]),
+        });
+
+        rt.run().unwrap();
+
+        assert_eq!(8, rt.output());
+        assert_eq!(42, rt.registers.data[2]);
+        // the freed slot is reused
+        assert_eq!(0, rt.registers.data[3]);
+        assert_eq!(
+            &HeapStats {
+                allocations: 2,
+                reallocations: 1,
+                frees: 1,
+                peak_live: 1,
+            },
+            rt.heap_stats()
+        );
+        assert_eq!(
+            vec![Leak {
+                handle: 0,
+                label: other,
+                length: 2
+            }],
+            rt.leaks()
+        );
+    }
+
+    #[test]
+    fn alloc_errors_test() {
+        let buffer = 8411;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(Integer::MAX)]),
+        });
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidHeapAccess,
+                format!(
+                    "heap entry size {} is larger than the maximum of {MAX_HEAP_ENTRY_SIZE}",
+                    Integer::MAX
+                )
+            )),
+            rt.run()
+        );
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(4)]),
+            1 => (Command::Realloc, [Argument::RawLabel(buffer), Argument::Raw(1 << 40)]),
+        });
+        let Ok(RunOutcome::Trapped(Fault::InvalidHeapAccess, error)) = rt.run() else {
+            panic!("expected an invalid heap access");
+        };
+        assert!(error.starts_with("heap entry size 1099511627776"));
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(4)]),
+            1 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(2)]),
+        });
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidHeapAccess,
+                String::from(":8411 is still allocated as heap entry 0, free it first")
+            )),
+            rt.run()
+        );
+        assert_eq!(1, rt.heap_stats().allocations);
+
+        let data = 8412;
+        let code = 8413;
+        for label in [data, code] {
+            let mut rt = Runtime::new(maplit::btreemap! {
+                0 => (Command::LabelledData(data), [Argument::Literal(vec![1]), Argument::None]),
+                1 => (Command::Label, [Argument::RawLabel(code), Argument::None]),
+                2 => (Command::Alloc, [Argument::RawLabel(label), Argument::Raw(4)]),
+            });
+            assert_eq!(
+                Ok(RunOutcome::Trapped(
+                    Fault::InvalidHeapAccess,
+                    format!(":{label} is already bound to data or code")
+                )),
+                rt.run()
+            );
+            assert_eq!(0, rt.heap_stats().allocations);
+        }
+    }
+
+    #[test]
+    fn use_after_free_test() {
+        let buffer = 8411;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(4)]),
+            1 => (Command::Free, [Argument::RawLabel(buffer), Argument::None]),
+            2 => (Command::Move, [Argument::Register(0), Argument::HeapDeref(buffer, 0)]),
+        });
+
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidHeapAccess,
+                String::from("unknown heap label :8411")
+            )),
+            rt.run()
+        );
+    }
+
+    #[test]
+    fn host_function_use_after_free_test() {
+        let buffer = 8411;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::Alloc, [Argument::RawLabel(buffer), Argument::Raw(4)]),
+            1 => (Command::Move, [Argument::Register(1), Argument::RawLabel(buffer)]),
+            2 => (Command::Free, [Argument::RawLabel(buffer), Argument::None]),
+            3 => (Command::Push, [Argument::Register(1), Argument::None]),
+            4 => (Command::Push, [Argument::Raw(42), Argument::None]),
+            5 => (Command::Function, [Argument::RawLabel(hash_label("itoa")), Argument::None]),
+        });
+
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidHeapAccess,
+                String::from("invalid heap handle 0")
+            )),
+            rt.run()
+        );
+        assert_eq!(vec![Vec::<Integer>::new()], rt.heap);
+    }
+
+    #[test]
+    fn host_function_missing_argument_test() {
+        for function in ["strlen", "send", "recv"] {
+            let mut rt = Runtime::new(maplit::btreemap! {
+                0 => (Command::Function, [Argument::RawLabel(hash_label(function)), Argument::None]),
+            });
+            assert_eq!(
+                Ok(RunOutcome::Trapped(
+                    Fault::StackUnderflow,
+                    String::from("stack underflow: missing function argument")
+                )),
+                rt.run()
+            );
+        }
+    }
+
+    #[test]
+    fn free_static_data_test() {
+        let data_str = 12529907765057034586;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::LabelledData(data_str), [Argument::Literal(vec![1]), Argument::None]),
+            1 => (Command::Free, [Argument::RawLabel(data_str), Argument::None]),
+        });
+
+        assert_eq!(
+            format!(":{data_str} is not an allocated heap entry"),
+            rt.run().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn external_function_call_print() {
+        let print_label = hash_label("print");
+        let data_str = 12529907765057034586;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            1 => (Command::LabelledData(data_str), [Argument::Literal("Hallo".chars().map(|x| x as Integer).collect()), Argument::None]),
+            2 => (Command::Push, [Argument::RawLabel(data_str), Argument::None]),
+            3 => (Command::Function, [Argument::RawLabel(print_label), Argument::None])
+        });
+
+        rt.run().unwrap();
+    }
+
+    #[test]
+    fn external_function_call_strings() {
+        let data_str = 12529907765057034586;
+        let number = 8411;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            1 => (Command::LabelledData(data_str), [Argument::Literal("n=".chars().map(|x| x as Integer).collect()), Argument::None]),
+            2 => (Command::LabelledData(number), [Argument::Literal(Vec::new()), Argument::None]),
+            3 => (Command::Push, [Argument::RawLabel(number), Argument::None]),
+            4 => (Command::Push, [Argument::Raw(42), Argument::None]),
+            5 => (Command::Function, [Argument::RawLabel(hash_label("itoa")), Argument::None]),
+            6 => (Command::Push, [Argument::RawLabel(data_str), Argument::None]),
+            7 => (Command::Push, [Argument::RawLabel(number), Argument::None]),
+            8 => (Command::Function, [Argument::RawLabel(hash_label("strcat")), Argument::None]),
+            9 => (Command::Push, [Argument::RawLabel(data_str), Argument::None]),
+            10 => (Command::Function, [Argument::RawLabel(hash_label("strlen")), Argument::None]),
+            11 => (Command::Pop, [Argument::Register(0), Argument::None]),
+        });
+
+        rt.run().unwrap();
+
+        assert_eq!(4, rt.output());
+        let heap_id = rt.label_references[&data_str] as usize;
+        assert_eq!(
+            "n=42",
+            decode_heap_binary_to_string(&rt.heap[heap_id]).unwrap()
+        );
+    }
+
+    #[test]
+    fn external_function_call_input() {
+        let line = 8411;
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::LabelledData(line), [Argument::Literal(Vec::new()), Argument::None]),
+            1 => (Command::Function, [Argument::RawLabel(hash_label("readint")), Argument::None]),
+            2 => (Command::Pop, [Argument::Register(1), Argument::None]),
+            3 => (Command::Pop, [Argument::Register(0), Argument::None]),
+            4 => (Command::Function, [Argument::RawLabel(hash_label("readchar")), Argument::None]),
+            5 => (Command::Pop, [Argument::Register(2), Argument::None]),
+            6 => (Command::Pop, [Argument::Register(3), Argument::None]),
+            7 => (Command::Push, [Argument::RawLabel(line), Argument::None]),
+            8 => (Command::Function, [Argument::RawLabel(hash_label("readline")), Argument::None]),
+            9 => (Command::Pop, [Argument::Register(4), Argument::None]),
+            10 => (Command::Push, [Argument::RawLabel(line), Argument::None]),
+            11 => (Command::Function, [Argument::RawLabel(hash_label("readline")), Argument::None]),
+            12 => (Command::Pop, [Argument::Register(5), Argument::None]),
+        })
+        .with_input(std::io::Cursor::new("123\nab\n"));
+
+        rt.run().unwrap();
+
+        assert_eq!(123, rt.output());
+        assert_eq!(INPUT_OK, rt.registers.data[1]);
+        assert_eq!(INPUT_OK, rt.registers.data[2]);
+        assert_eq!('a' as Integer, rt.registers.data[3]);
+        assert_eq!(INPUT_OK, rt.registers.data[4]);
+        assert_eq!(INPUT_EOF, rt.registers.data[5]);
+        let heap_id = rt.label_references[&line] as usize;
+        assert_eq!(vec!['b' as Integer], rt.heap[heap_id]);
+    }
+
+    #[test]
+    fn console_shares_input() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(CONSOLE_ADDRESS)]),
+            1 => (Command::Load, [Argument::Register(2), Argument::Memory(1, Offset::Raw(0))]),
+            2 => (Command::Function, [Argument::RawLabel(hash_label("readchar")), Argument::None]),
+            3 => (Command::Pop, [Argument::Register(3), Argument::None]),
+            4 => (Command::Pop, [Argument::Register(0), Argument::None]),
+        })
+        .with_input(std::io::Cursor::new("ab"));
+
+        rt.run().unwrap();
+
+        assert_eq!('a' as Integer, rt.registers.data[2]);
+        assert_eq!('b' as Integer, rt.output());
+    }
+
+    #[test]
+    fn external_function_call_files() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("shitty_runtime_{}.txt", std::process::id()));
+        let path_label = 8411;
+        let data = 8412;
+        let read_back = 8413;
+        let fopen = Argument::RawLabel(hash_label("fopen"));
+
+        let mut rt = Runtime::new(maplit::btreemap! {
+            0 => (Command::LabelledData(path_label), [Argument::Literal(path.to_str().unwrap().chars().map(|x| x as Integer).collect()), Argument::None]),
+            1 => (Command::LabelledData(data), [Argument::Literal("Hallo".chars().map(|x| x as Integer).collect()), Argument::None]),
+            2 => (Command::LabelledData(read_back), [Argument::Literal(Vec::new()), Argument::None]),
+            3 => (Command::Push, [Argument::RawLabel(path_label), Argument::None]),
+            4 => (Command::Push, [Argument::Raw(OPEN_WRITE), Argument::None]),
+            5 => (Command::Function, [fopen.clone(), Argument::None]),
+            6 => (Command::Pop, [Argument::Register(1), Argument::None]),
+            7 => (Command::Pop, [Argument::Register(2), Argument::None]),
+            8 => (Command::Push, [Argument::Register(2), Argument::None]),
+            9 => (Command::Push, [Argument::RawLabel(data), Argument::None]),
+            10 => (Command::Function, [Argument::RawLabel(hash_label("fwrite")), Argument::None]),
+            11 => (Command::Push, [Argument::Register(2), Argument::None]),
+            12 => (Command::Function, [Argument::RawLabel(hash_label("fclose")), Argument::None]),
+            13 => (Command::Push, [Argument::RawLabel(path_label), Argument::None]),
+            14 => (Command::Push, [Argument::Raw(OPEN_READ), Argument::None]),
+            15 => (Command::Function, [fopen, Argument::None]),
+            16 => (Command::Pop, [Argument::Register(1), Argument::None]),
+            17 => (Command::Pop, [Argument::Register(2), Argument::None]),
+            18 => (Command::Push, [Argument::RawLabel(read_back), Argument::None]),
+            19 => (Command::Push, [Argument::Register(2), Argument::None]),
+            20 => (Command::Push, [Argument::Raw(100), Argument::None]),
+            21 => (Command::Function, [Argument::RawLabel(hash_label("fread")), Argument::None]),
+            22 => (Command::Pop, [Argument::Register(0), Argument::None]),
+        });
+
+        let mut denied = rt.clone();
+        assert!(denied.run().unwrap_err().starts_with("file access denied"));
+
+        rt = rt.with_file_system(
+            FileSystemPolicy::default()
+                .allow_read(&dir)
+                .unwrap()
+                .allow_write(&dir)
+                .unwrap(),
+        );
+        rt.run().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(5, rt.output());
+        assert_eq!(1, rt.registers.data[1]);
+        let heap_id = rt.label_references[&read_back] as usize;
+        assert_eq!(
+            "Hallo",
+            decode_heap_binary_to_string(&rt.heap[heap_id]).unwrap()
+        );
+    }
+
+    #[test]
+    fn random_device() {
+        let program = btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(RANDOM_ADDRESS)]),
+            1 => (Command::Store, [Argument::Memory(1, Offset::Raw(0)), Argument::Raw(1234)]),
+            2 => (Command::Load, [Argument::Register(0), Argument::Memory(1, Offset::Raw(0))]),
+        };
+
+        let mut rt = Runtime::new(program.clone());
+        rt.run().unwrap();
+        let mut again = Runtime::new(program);
+        again.run().unwrap();
+
+        assert_ne!(0, rt.output());
+        assert_eq!(rt.output(), again.output());
+    }
+
+    #[test]
+    fn console_and_tick_devices() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Clone, Default)]
+        struct Output(Rc<RefCell<Vec<u8>>>);
+
+        impl std::io::Write for Output {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Output::default();
+        let console = Console::new(Input::new(std::io::Cursor::new("A")), output.clone());
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(CONSOLE_ADDRESS)]),
+            1 => (Command::Load, [Argument::Register(2), Argument::Memory(1, Offset::Raw(0))]),
+            2 => (Command::Add, [Argument::Register(2), Argument::Raw(1)]),
+            3 => (Command::Store, [Argument::Memory(1, Offset::Raw(0)), Argument::Register(2)]),
+            4 => (Command::Store, [Argument::Memory(1, Offset::Raw(1)), Argument::Raw(42)]),
+            5 => (Command::Load, [Argument::Register(3), Argument::Memory(1, Offset::Raw(0))]),
+            6 => (Command::Move, [Argument::Register(1), Argument::Raw(TICK_ADDRESS)]),
+            7 => (Command::Load, [Argument::Register(0), Argument::Memory(1, Offset::Raw(0))]),
+        })
+        .with_device(CONSOLE_ADDRESS, Box::new(console))
+        .with_input(std::io::Cursor::new("Z"));
+
+        rt.run().unwrap();
+
+        assert_eq!(b"B42".to_vec(), *output.0.borrow());
+        assert_eq!(CONSOLE_EOF, rt.registers.data[3]);
+        assert_eq!(7, rt.output());
+    }
+
+    #[test]
+    fn framebuffer_flush() {
+        use std::rc::Rc;
+
+        let flushed = Rc::new(Cell::new(0));
+        let counter = flushed.clone();
+        let framebuffer = Framebuffer::default().on_flush(move |_, frame| {
+            counter.set(frame.pixel(2, 0).unwrap());
+            Ok(())
+        });
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(FRAMEBUFFER_ADDRESS)]),
+            1 => (Command::Store, [Argument::Memory(1, Offset::Raw(2)), Argument::Raw(12)]),
+            2 => (Command::Flush, [Argument::None, Argument::None]),
+            3 => (Command::Store, [Argument::Memory(1, Offset::Raw(3)), Argument::Raw(16)]),
+        })
+        .with_device(FRAMEBUFFER_ADDRESS, Box::new(framebuffer.clone()));
+
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidMemoryAccess,
+                String::from("invalid palette index 16")
+            )),
+            rt.run()
+        );
+        assert_eq!(12, flushed.get());
+        assert!(!framebuffer.dirty());
+    }
+
+    #[test]
+    fn embedder_device() {
+        #[derive(Clone)]
+        struct Doubler;
+
+        impl Device for Doubler {
+            fn size(&self) -> Integer {
+                4
+            }
+
+            fn read(&mut self, offset: Integer) -> Result<Integer, Error> {
+                Ok(offset * 2)
+            }
+
+            fn write(&mut self, _offset: Integer, _value: Integer) -> Result<(), Error> {
+                Err(String::from("read only device"))
+            }
+        }
+
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(1), Argument::Raw(100)]),
+            1 => (Command::Load, [Argument::Register(0), Argument::Memory(1, Offset::Raw(3))]),
+            2 => (Command::Store, [Argument::Memory(1, Offset::Raw(4)), Argument::Raw(1)]),
+            3 => (Command::Store, [Argument::Memory(1, Offset::Raw(0)), Argument::Raw(1)]),
+        })
+        .with_device(100, Box::new(Doubler));
+
+        assert_eq!(
+            Ok(RunOutcome::Trapped(
+                Fault::InvalidMemoryAccess,
+                String::from("read only device")
+            )),
+            rt.run()
+        );
+        assert_eq!(6, rt.output());
+        assert_eq!(1, rt.memory().read(104).unwrap());
+    }
+
+    #[test]
+    fn cycle_counter() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(0), Argument::Raw(6)]),
+            1 => (Command::Multiply, [Argument::Register(0), Argument::Raw(7)]),
+            2 => (Command::Divide, [Argument::Register(0), Argument::Raw(2)]),
+            3 => (Command::Move, [Argument::Register(1), Argument::CycleCounter]),
+        });
+
+        rt.run().unwrap();
+
+        assert_eq!(21, rt.output());
+        assert_eq!(14, rt.registers.data[1]);
+        assert_eq!(15, rt.cycles());
+    }
+
+    #[test]
+    fn custom_cost_table() {
+        let mut rt = Runtime::new(btreemap! {
+            0 => (Command::Move, [Argument::Register(0), Argument::Raw(6)]),
+            1 => (Command::Multiply, [Argument::Register(0), Argument::Raw(7)]),
+        })
+        .with_cost_table(CostTable {
+            multiply: 1,
+            ..CostTable::default()
+        });
+
+        rt.run().unwrap();
 
-        u64::try_from(output).unwrap();
+        assert_eq!(2, rt.cycles());
     }
 }
@@ -0,0 +1,60 @@
+use shitty_types::{Error, Integer};
+
+pub const DEFAULT_MEMORY_SIZE: usize = 65536;
+
+/// Flat word-addressable memory, accessed with `ld` and `st`.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    data: Vec<Integer>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new(DEFAULT_MEMORY_SIZE)
+    }
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        Memory {
+            data: vec![0; size],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn read(&self, address: Integer) -> Result<Integer, Error> {
+        usize::try_from(address)
+            .ok()
+            .and_then(|address| self.data.get(address))
+            .copied()
+            .ok_or_else(|| out_of_bounds(address))
+    }
+
+    pub fn write(&mut self, address: Integer, value: Integer) -> Result<(), Error> {
+        let slot = usize::try_from(address)
+            .ok()
+            .and_then(|address| self.data.get_mut(address))
+            .ok_or_else(|| out_of_bounds(address))?;
+        *slot = value;
+        Ok(())
+    }
+}
+
+fn out_of_bounds(address: Integer) -> Error {
+    format!("memory access out of bounds: address {address}")
+}
+
+#[test]
+fn read_write_memory() {
+    let mut memory = Memory::new(4);
+
+    memory.write(3, 42).unwrap();
+
+    assert_eq!(42, memory.read(3).unwrap());
+    assert_eq!(0, memory.read(0).unwrap());
+    assert!(memory.read(4).is_err());
+    assert!(memory.write(4, 1).is_err());
+}
@@ -0,0 +1,227 @@
+use shitty_types::{hash_label, Integer, Literal, Stack};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::{decode_heap_binary_to_string, ExternalFunction, Fault, HostHeap, RuntimeError};
+
+/// Host functions working on heap entries.
+///
+/// Arguments are pushed in order before `func` and popped by the function,
+/// results are pushed back onto the stack. Functions that produce text write
+/// into a destination entry passed by the caller instead of creating a new one.
+pub fn string_functions() -> BTreeMap<Integer, Box<dyn ExternalFunction>> {
+    let mut functions: BTreeMap<Integer, Box<dyn ExternalFunction>> = BTreeMap::new();
+
+    // push :s -> length
+    functions.insert(hash_label("strlen"), Box::new(strlen));
+    // push :dest, push :src -> dest = dest + src
+    functions.insert(hash_label("strcat"), Box::new(strcat));
+    // push :dest, push :src -> dest = src
+    functions.insert(hash_label("strcpy"), Box::new(strcpy));
+    // push :a, push :b -> 0 if equal, 1 if a < b, 2 if a > b
+    functions.insert(hash_label("strcmp"), Box::new(strcmp));
+    // push :dest, push :src, push start, push length -> dest = src[start..start + length]
+    functions.insert(hash_label("substr"), Box::new(substr));
+    // push :dest, push n -> dest = n in decimal
+    functions.insert(hash_label("itoa"), Box::new(itoa));
+    // push :s -> value, 1 on success or 0 if :s is not a decimal number
+    functions.insert(hash_label("atoi"), Box::new(atoi));
+    // push :s -> s in uppercase
+    functions.insert(hash_label("upper"), Box::new(upper));
+    // push :s -> s in lowercase
+    functions.insert(hash_label("lower"), Box::new(lower));
+
+    functions
+}
+
+pub(crate) fn pop(stack: &mut Stack) -> Result<Integer, RuntimeError> {
+    stack.pop().ok_or_else(missing_argument)
+}
+
+pub(crate) fn missing_argument() -> RuntimeError {
+    RuntimeError::Fault(
+        Fault::StackUnderflow,
+        String::from("stack underflow: missing function argument"),
+    )
+}
+
+pub(crate) fn encode_string(string: &str) -> Literal {
+    string.chars().map(|c| c as Integer).collect()
+}
+
+fn strlen(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let handle = pop(stack)?;
+    let length = heap.entry(handle)?.len();
+    stack.push(length as Integer);
+    Ok(())
+}
+
+fn strcat(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let src = pop(stack)?;
+    let dest = pop(stack)?;
+    let mut data = heap.entry(dest)?.clone();
+    data.extend_from_slice(heap.entry(src)?);
+    heap.store(dest, data)?;
+    Ok(())
+}
+
+fn strcpy(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let src = pop(stack)?;
+    let dest = pop(stack)?;
+    let data = heap.entry(src)?.clone();
+    heap.store(dest, data)?;
+    Ok(())
+}
+
+fn strcmp(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let b = pop(stack)?;
+    let a = pop(stack)?;
+    let result = match heap.entry(a)?.cmp(heap.entry(b)?) {
+        Ordering::Equal => 0,
+        Ordering::Less => 1,
+        Ordering::Greater => 2,
+    };
+    stack.push(result);
+    Ok(())
+}
+
+fn substr(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let length = pop(stack)? as usize;
+    let start = pop(stack)? as usize;
+    let src = pop(stack)?;
+    let dest = pop(stack)?;
+    let data = heap.entry(src)?;
+    let part = start
+        .checked_add(length)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| {
+            format!(
+                "substring {start}+{length} out of bounds for length {}",
+                data.len()
+            )
+        })?
+        .to_vec();
+    heap.store(dest, part)?;
+    Ok(())
+}
+
+fn itoa(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let value = pop(stack)?;
+    let dest = pop(stack)?;
+    heap.store(dest, encode_string(&value.to_string()))?;
+    Ok(())
+}
+
+fn atoi(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let handle = pop(stack)?;
+    let parsed = decode_heap_binary_to_string(heap.entry(handle)?)
+        .ok()
+        .and_then(|string| string.trim().parse::<Integer>().ok());
+    match parsed {
+        Some(value) => {
+            stack.push(value);
+            stack.push(1);
+        }
+        None => {
+            stack.push(0);
+            stack.push(0);
+        }
+    }
+    Ok(())
+}
+
+fn upper(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    map_chars(heap, stack, |c| c.to_uppercase().next().unwrap_or(c))
+}
+
+fn lower(heap: &mut HostHeap, stack: &mut Stack) -> Result<(), RuntimeError> {
+    map_chars(heap, stack, |c| c.to_lowercase().next().unwrap_or(c))
+}
+
+fn map_chars(
+    heap: &mut HostHeap,
+    stack: &mut Stack,
+    f: fn(char) -> char,
+) -> Result<(), RuntimeError> {
+    let handle = pop(stack)?;
+    heap.update(handle, |data| {
+        for value in data.iter_mut() {
+            if let Some(c) = u32::try_from(*value).ok().and_then(char::from_u32) {
+                *value = f(c) as Integer;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+fn call(
+    function: fn(&mut HostHeap, &mut Stack) -> Result<(), RuntimeError>,
+    heap: &mut shitty_types::Heap,
+    stack: &mut Stack,
+) -> Result<(), RuntimeError> {
+    function(
+        &mut HostHeap::new(heap, &crate::Allocations::default()),
+        stack,
+    )
+}
+
+#[test]
+fn string_functions_test() {
+    let mut heap = vec![encode_string("Hello"), encode_string(", World"), vec![]];
+    let mut stack = vec![0, 1];
+
+    call(strcat, &mut heap, &mut stack).unwrap();
+    assert_eq!(encode_string("Hello, World"), heap[0]);
+
+    stack.extend([2, 0]);
+    call(strcpy, &mut heap, &mut stack).unwrap();
+    assert_eq!(heap[0], heap[2]);
+
+    stack.extend([0, 2]);
+    call(strcmp, &mut heap, &mut stack).unwrap();
+    assert_eq!(vec![0], stack);
+    stack.clear();
+
+    stack.extend([1, 0, 7, 5]);
+    call(substr, &mut heap, &mut stack).unwrap();
+    assert_eq!(encode_string("World"), heap[1]);
+
+    stack.extend([2, 1]);
+    call(strcmp, &mut heap, &mut stack).unwrap();
+    assert_eq!(vec![1], stack);
+    stack.clear();
+
+    stack.push(1);
+    call(upper, &mut heap, &mut stack).unwrap();
+    assert_eq!(encode_string("WORLD"), heap[1]);
+
+    stack.push(0);
+    call(lower, &mut heap, &mut stack).unwrap();
+    assert_eq!(encode_string("hello, world"), heap[0]);
+
+    stack.push(0);
+    call(strlen, &mut heap, &mut stack).unwrap();
+    assert_eq!(vec![12], stack);
+    stack.clear();
+
+    stack.extend([1, 0, 13, 1]);
+    assert!(call(substr, &mut heap, &mut stack).is_err());
+}
+
+#[test]
+fn integer_conversion_test() {
+    let mut heap = vec![vec![], encode_string("12x")];
+    let mut stack = vec![0, 1234];
+
+    call(itoa, &mut heap, &mut stack).unwrap();
+    assert_eq!(encode_string("1234"), heap[0]);
+
+    stack.push(0);
+    call(atoi, &mut heap, &mut stack).unwrap();
+    assert_eq!(vec![1234, 1], stack);
+    stack.clear();
+
+    stack.push(1);
+    call(atoi, &mut heap, &mut stack).unwrap();
+    assert_eq!(vec![0, 0], stack);
+}
@@ -0,0 +1,59 @@
+use shitty_types::Integer;
+
+/// Vector the timer interrupt is delivered on unless another one is chosen.
+pub const TIMER_VECTOR: Integer = 32;
+
+/// A virtual timer that raises an interrupt every `interval` executed instructions.
+///
+/// Time is counted in instructions instead of wall-clock time, so a program
+/// is preempted at the same points on every run. A handler that runs for
+/// longer than the interval is interrupted again right after its `iret`.
+#[derive(Debug, Clone)]
+pub struct Timer {
+    interval: u64,
+    vector: Integer,
+    next: u64,
+}
+
+impl Timer {
+    pub fn new(interval: u64) -> Self {
+        let interval = interval.max(1);
+        Timer {
+            interval,
+            vector: TIMER_VECTOR,
+            next: interval,
+        }
+    }
+
+    pub fn with_vector(mut self, vector: Integer) -> Self {
+        self.vector = vector;
+        self
+    }
+
+    pub fn vector(&self) -> Integer {
+        self.vector
+    }
+
+    /// Whether the timer has expired after `instructions`, it stays pending until acknowledged.
+    pub fn pending(&self, instructions: u64) -> bool {
+        instructions >= self.next
+    }
+
+    /// Restarts the timer after its interrupt was delivered.
+    pub fn acknowledge(&mut self, instructions: u64) {
+        self.next = instructions + self.interval;
+    }
+}
+
+#[test]
+fn timer_stays_pending_until_acknowledged() {
+    let mut timer = Timer::new(3);
+
+    assert!(!timer.pending(2));
+    assert!(timer.pending(3));
+    assert!(timer.pending(5));
+
+    timer.acknowledge(5);
+    assert!(!timer.pending(7));
+    assert!(timer.pending(8));
+}
@@ -1,8 +1,10 @@
+#![allow(clippy::clone_on_copy, clippy::upper_case_acronyms)]
+
 use crate::parser::Rule;
 use pest::Span;
 use pest_ast::FromPest;
 
-fn span_into_str(span: Span) -> &str {
+fn span_into_str(span: Span<'_>) -> &str {
     span.as_str()
 }
 
@@ -150,9 +152,8 @@ impl Expression {
 
 impl Term {
     pub fn add_heap_values<'a>(&'a mut self, list: &mut Vec<&'a mut Term>) {
-        match self {
-            Term::String(_) => list.push(self),
-            _ => {}
+        if let Term::String(_) = self {
+            list.push(self)
         }
     }
 }
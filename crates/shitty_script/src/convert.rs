@@ -7,6 +7,9 @@ use crate::ast::{
     StringLiteral, Term,
 };
 
+/// The label the script starts at, no function can have this name.
+const MAIN_LABEL: &str = "@main";
+
 pub fn script_to_program(script: &mut Script) -> Result<shitty_types::Program, anyhow::Error> {
     let mut commands: Vec<(Command, [Argument; 2])> = Vec::new();
 
@@ -16,7 +19,7 @@ pub fn script_to_program(script: &mut Script) -> Result<shitty_types::Program, a
 
     commands.push((
         Command::Branch,
-        [Argument::RawLabel(hash_label(".main")), Argument::None],
+        [Argument::RawLabel(hash_label(MAIN_LABEL)), Argument::None],
     ));
 
     for line in script.program.lines.iter_mut() {
@@ -27,7 +30,7 @@ pub fn script_to_program(script: &mut Script) -> Result<shitty_types::Program, a
                     x.body.add_heap_values(&mut heap_values);
                     for (index, value) in heap_values.into_iter().enumerate() {
                         let mut key = x.ident.ident.clone();
-                        key.push_str("_");
+                        key.push('_');
                         key.push_str(&index.to_string());
 
                         hoisted_static_values.insert(key.clone(), value.clone());
@@ -114,7 +117,7 @@ pub fn script_to_program(script: &mut Script) -> Result<shitty_types::Program, a
     }
     commands.push((
         Command::Label,
-        [Argument::RawLabel(hash_label(".main")), Argument::None],
+        [Argument::RawLabel(hash_label(MAIN_LABEL)), Argument::None],
     ));
 
     for line in other_lines {
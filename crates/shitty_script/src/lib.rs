@@ -197,7 +197,7 @@ fn convert_script_to_commands() {
 
     println!("{}", shitty_types::format_program(&program));
 
-    let expected_program = r#"b :.main
+    let expected_program = r#"b :@main
 bar_0: db "hallo"
 foo:
     push #1234
@@ -209,7 +209,7 @@ echo:
     pop r1
     push r1
     ret
-.main:
+@main:
     push #1234
     call :echo
     pop r0
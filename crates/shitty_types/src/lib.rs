@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::hash::{DefaultHasher, Hash, Hasher};
+use std::str::FromStr;
 
 pub type Error = String;
 
@@ -14,6 +15,9 @@ pub type Stack = Vec<Integer>;
 pub type Program = BTreeMap<Integer, (Command, [Argument; 2])>;
 pub type Literal = Vec<Integer>;
 
+/// The most words a heap entry can hold, a program asking for more is most likely wrong.
+pub const MAX_HEAP_ENTRY_SIZE: usize = 1 << 24;
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Command {
@@ -21,7 +25,7 @@ pub enum Command {
     Noop,
     #[serde(rename = "lbl")]
     Label,
-    #[serde(rename = "ld")]
+    #[serde(rename = "db")]
     LabelledData(Integer),
     #[serde(rename = "b")]
     Branch,
@@ -58,6 +62,35 @@ pub enum Command {
     Function,
     #[serde(rename = "ret")]
     Return,
+    #[serde(rename = "ld")]
+    Load,
+    #[serde(rename = "st")]
+    Store,
+    Alloc,
+    Free,
+    Realloc,
+    #[serde(rename = "len")]
+    Length,
+    #[serde(rename = "hlt")]
+    Halt,
+    Exit,
+    #[serde(rename = "int")]
+    Interrupt,
+    #[serde(rename = "iret")]
+    InterruptReturn,
+    #[serde(rename = "ivt")]
+    SetInterruptVector,
+    #[serde(rename = "ei")]
+    EnableInterrupts,
+    #[serde(rename = "di")]
+    DisableInterrupts,
+    Flush,
+    Spawn,
+    Join,
+    #[serde(rename = "cas")]
+    CompareAndSwap,
+    #[serde(rename = "xadd")]
+    FetchAdd,
 }
 
 impl Command {
@@ -99,38 +132,130 @@ pub enum Argument {
     #[serde(rename = "lit")]
     Literal(Literal),
     HeapDeref(Integer, usize),
+    HeapIndex(Integer, u8),
+    #[serde(rename = "cyc")]
+    CycleCounter,
+    #[serde(rename = "mem")]
+    Memory(u8, Offset),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Offset {
+    Raw(Integer),
+    #[serde(rename = "reg")]
+    Register(u8),
+}
+
+/// The base numbers are written in when formatting a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Radix {
+    Binary,
+    Octal,
+    #[default]
+    Decimal,
+    Hexadecimal,
+}
+
+impl Radix {
+    /// Formats `value` with the prefix the parser expects, like `0xFF`.
+    pub fn format(&self, value: Integer) -> String {
+        match self {
+            Radix::Binary => format!("0b{value:b}"),
+            Radix::Octal => format!("0o{value:o}"),
+            Radix::Decimal => value.to_string(),
+            Radix::Hexadecimal => format!("0x{value:X}"),
+        }
+    }
+}
+
+impl FromStr for Radix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bin" => Ok(Radix::Binary),
+            "oct" => Ok(Radix::Octal),
+            "dec" => Ok(Radix::Decimal),
+            "hex" => Ok(Radix::Hexadecimal),
+            other => Err(format!(
+                "invalid radix `{other}`, expected bin, oct, dec or hex"
+            )),
+        }
+    }
 }
 
 impl Argument {
     pub fn format(&self) -> String {
+        self.format_radix(Radix::Decimal)
+    }
+
+    /// Like `format`, but writes numbers in `radix`.
+    pub fn format_radix(&self, radix: Radix) -> String {
+        self.format_with(&SymbolTable::default(), radix)
+    }
+
+    /// Like `format_radix`, but prints labels by their name when `symbols` knows it.
+    pub fn format_with(&self, symbols: &SymbolTable, radix: Radix) -> String {
+        let name = |label: &Integer| symbols.label_name(*label);
+        let number = |value: &Integer| radix.format(*value);
         match self {
             Argument::None => "".to_string(),
-            Argument::Raw(n) => format!("#{n}"),
+            Argument::Raw(n) => format!("#{}", number(n)),
             Argument::Register(r) => format!("r{r}"),
             Argument::HeapRef(h) => h.to_string(),
             Argument::Literal(l) if l.is_empty() => String::from(r#"db """#),
-            Argument::Literal(l) => {
-                let out: Option<String> = l
-                    .iter()
-                    .map(|b| (*b).try_into().ok())
-                    .map(|c: Option<u32>| c.map(char::from_u32).flatten())
-                    .collect();
-                let value = if let Some(valid_string) = out {
-                    format!("{:?}", valid_string)
-                } else {
-                    let data: Vec<_> = l.iter().map(|b| b.to_string()).collect();
-                    data.join(",")
-                };
-
-                let mut return_value = String::from("db ");
-                return_value.push_str(&value);
-                return_value
+            Argument::Literal(l) if l.iter().all(|value| *value == 0) => {
+                format!("resw {}", number(&(l.len() as Integer)))
+            }
+            Argument::Literal(l) => format!("db {}", format_data(l, radix)),
+            Argument::HeapDeref(h, 0) => format!("[:{}]", name(h)),
+            Argument::HeapDeref(h, i) => {
+                format!("[:{} + {}]", name(h), number(&(*i as Integer)))
+            }
+            Argument::HeapIndex(h, r) => format!("[:{} + r{r}]", name(h)),
+            Argument::RawLabel(l) => format!(":{}", name(l)),
+            Argument::CycleCounter => "cyc".to_string(),
+            Argument::Memory(base, Offset::Raw(0)) => format!("[r{base}]"),
+            Argument::Memory(base, Offset::Raw(offset)) => {
+                format!("[r{base} + {}]", number(offset))
+            }
+            Argument::Memory(base, Offset::Register(offset)) => format!("[r{base} + r{offset}]"),
+        }
+    }
+}
+
+/// `db` items for `data`, runs of printable characters are written as a string
+/// and everything else as numbers, so the assembler reads back the same data.
+fn format_data(data: &[Integer], radix: Radix) -> String {
+    let mut items = Vec::new();
+    let mut text = String::new();
+    for value in data {
+        match printable(*value) {
+            Some(c) => match c {
+                '"' => text.push_str("\\\""),
+                '\\' => text.push_str("\\\\"),
+                '\n' => text.push_str("\\n"),
+                '\t' => text.push_str("\\t"),
+                c => text.push(c),
+            },
+            None => {
+                if !text.is_empty() {
+                    items.push(format!("\"{}\"", std::mem::take(&mut text)));
+                }
+                items.push(radix.format(*value));
             }
-            Argument::HeapDeref(h, 0) => format!("[:{h}]"),
-            Argument::HeapDeref(h, i) => format!("[:{h} + {i}]"),
-            Argument::RawLabel(l) => format!(":{l}"),
         }
     }
+    if !text.is_empty() {
+        items.push(format!("\"{text}\""));
+    }
+    items.join(",")
+}
+
+fn printable(value: Integer) -> Option<char> {
+    let c = char::from_u32(value.try_into().ok()?)?;
+    (c == ' ' || c == '\n' || c == '\t' || c.is_ascii_graphic() || c.is_alphanumeric()).then_some(c)
 }
 
 impl Argument {
@@ -147,31 +272,124 @@ impl Argument {
     }
 }
 
+/// Names from the assembly source, so that a program can be printed with them again.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SymbolTable {
+    /// Label names by their hash.
+    pub labels: BTreeMap<Integer, String>,
+    /// Constants defined with `.equ NAME value` or `NAME = value`.
+    pub constants: BTreeMap<String, Integer>,
+    /// The source text of immediates that were not written as a plain number, by line.
+    pub immediates: BTreeMap<Integer, [Option<String>; 2]>,
+}
+
+impl SymbolTable {
+    pub fn label_name(&self, label: Integer) -> String {
+        self.labels
+            .get(&label)
+            .cloned()
+            .unwrap_or_else(|| label.to_string())
+    }
+
+    fn format_argument(
+        &self,
+        line: Integer,
+        index: usize,
+        argument: &Argument,
+        radix: Radix,
+    ) -> String {
+        let source = self
+            .immediates
+            .get(&line)
+            .and_then(|immediates| immediates[index].as_ref());
+        match (argument, source) {
+            (Argument::Raw(_), Some(source)) => format!("#{source}"),
+            _ => argument.format_with(self, radix),
+        }
+    }
+}
+
+/// Where the data of a `db` line is put on the heap.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DataEntry {
+    pub label: Integer,
+    /// The line the data is declared on.
+    pub line: Integer,
+    /// The heap entry the data is put in, the label refers to it.
+    pub handle: Integer,
+    /// The number of words of the data.
+    pub length: usize,
+}
+
+/// Where the data of `program` is put on the heap before the program starts.
+///
+/// The data is put in program order, starting at handle 0, whether or not the
+/// line is ever executed.
+pub fn data_layout(program: &Program) -> Vec<DataEntry> {
+    program
+        .iter()
+        .filter_map(|(line, command)| match command {
+            (Command::LabelledData(label), [Argument::Literal(data), _]) => {
+                Some((*line, *label, data.len()))
+            }
+            _ => None,
+        })
+        .enumerate()
+        .map(|(handle, (line, label, length))| DataEntry {
+            label,
+            line,
+            handle: handle as Integer,
+            length,
+        })
+        .collect()
+}
+
 pub fn hash_label(label: &str) -> u64 {
     let mut hasher = DefaultHasher::new();
     label.hash(&mut hasher);
-    let hash = hasher.finish();
-    hash
+    hasher.finish()
 }
 
 pub fn format_program(program: &Program) -> String {
+    format_program_with_symbols(program, &SymbolTable::default(), Radix::Decimal)
+}
+
+/// Formats `program` with the label and constant names from the assembly source.
+///
+/// Numbers are written in `radix`, unless the source wrote them as an expression.
+/// Data lines are put in a `.data` section and the other lines in a `.text` section.
+pub fn format_program_with_symbols(
+    program: &Program,
+    symbols: &SymbolTable,
+    radix: Radix,
+) -> String {
     let mut s = String::new();
 
-    for (_i, (command, [arg0, arg1])) in program.iter() {
+    for (name, value) in symbols.constants.iter() {
+        s.push_str(format!(".equ {name} {}\n", radix.format(*value)).as_str());
+    }
+
+    let mut data_section = false;
+    for (i, (command, [arg0, arg1])) in program.iter() {
+        let is_data = matches!(command, Command::LabelledData(_));
+        if is_data != data_section {
+            s.push_str(if is_data { ".data\n" } else { ".text\n" });
+            data_section = is_data;
+        }
+
+        let arg0 = symbols.format_argument(*i, 0, arg0, radix);
+        let arg1 = symbols.format_argument(*i, 1, arg1, radix);
         match command {
-            Command::Label => match arg0 {
-                Argument::RawLabel(label) => s.push_str(format!("{label}:\n").as_str()),
-                _ => unreachable!(),
-            },
+            Command::Label => s.push_str(format!("{}:\n", arg0.trim_start_matches(':')).as_str()),
             Command::LabelledData(label) => {
                 let mut formatted_line = String::new();
 
-                formatted_line.push_str(format!("{label}: ").as_str());
-                formatted_line.push_str(&arg0.format());
+                formatted_line.push_str(format!("{}: ", symbols.label_name(*label)).as_str());
+                formatted_line.push_str(&arg0);
                 formatted_line.push(' ');
-                formatted_line.push_str(&arg1.format());
+                formatted_line.push_str(&arg1);
 
-                s.push_str(&formatted_line.trim_end());
+                s.push_str(formatted_line.trim_end());
                 s.push('\n');
             }
             _ => {
@@ -179,11 +397,11 @@ pub fn format_program(program: &Program) -> String {
                 formatted_line.push_str("    ");
                 formatted_line.push_str(&command.to_name());
                 formatted_line.push(' ');
-                formatted_line.push_str(&arg0.format());
+                formatted_line.push_str(&arg0);
                 formatted_line.push(' ');
-                formatted_line.push_str(&arg1.format());
+                formatted_line.push_str(&arg1);
 
-                s.push_str(&formatted_line.trim_end());
+                s.push_str(formatted_line.trim_end());
                 s.push('\n');
             }
         }
@@ -196,6 +414,21 @@ pub fn format_program(program: &Program) -> String {
 fn test_command_to_name() {
     assert_eq!(Command::Add.to_name(), "add");
     assert_eq!(Command::Subtract.to_name(), "sub");
+    assert_eq!(Command::Load.to_name(), "ld");
+    assert_eq!(Command::Store.to_name(), "st");
+    assert_eq!(Command::Realloc.to_name(), "realloc");
+    assert_eq!(Command::Length.to_name(), "len");
+    assert_eq!(Command::Halt.to_name(), "hlt");
+    assert_eq!(Command::Exit.to_name(), "exit");
+    assert_eq!(Command::Interrupt.to_name(), "int");
+    assert_eq!(Command::InterruptReturn.to_name(), "iret");
+    assert_eq!(Command::SetInterruptVector.to_name(), "ivt");
+    assert_eq!(Command::EnableInterrupts.to_name(), "ei");
+    assert_eq!(Command::DisableInterrupts.to_name(), "di");
+    assert_eq!(Command::Flush.to_name(), "flush");
+    assert_eq!(Command::Spawn.to_name(), "spawn");
+    assert_eq!(Command::CompareAndSwap.to_name(), "cas");
+    assert_eq!(Command::FetchAdd.to_name(), "xadd");
     assert_eq!(Command::LabelledData(8421).to_name(), "8421: db")
 }
 
@@ -207,21 +440,54 @@ fn test_argument_format() {
     assert_eq!(Argument::RawLabel(123456).format(), ":123456");
     assert_eq!(Argument::HeapDeref(123456, 0).format(), "[:123456]");
     assert_eq!(Argument::HeapDeref(123456, 12).format(), "[:123456 + 12]");
+    assert_eq!(Argument::HeapIndex(123456, 3).format(), "[:123456 + r3]");
+    assert_eq!(Argument::CycleCounter.format(), "cyc");
+    assert_eq!(Argument::Memory(1, Offset::Raw(0)).format(), "[r1]");
+    assert_eq!(Argument::Memory(1, Offset::Raw(8)).format(), "[r1 + 8]");
+    assert_eq!(
+        Argument::Memory(1, Offset::Register(2)).format(),
+        "[r1 + r2]"
+    );
     assert_eq!(
         Argument::Literal(vec![116, 101, 115, 116, 105, 110, 103]).format(),
         r#"db "testing""#
     );
     assert_eq!(
         Argument::Literal(vec![116, 101, 115, 116, 105, 110, 103, 9410051]).format(),
-        "db 116,101,115,116,105,110,103,9410051"
+        r#"db "testing",9410051"#
     );
     assert_eq!(
         Argument::Literal(vec![1, 0, 1, 0, 1, 0]).format(),
-        r#"db "\u{1}\0\u{1}\0\u{1}\0""#
+        "db 1,0,1,0,1,0"
+    );
+    assert_eq!(
+        Argument::Literal("say \"hi\"\\\n\0".chars().map(|c| c as Integer).collect()).format(),
+        r#"db "say \"hi\"\\\n",0"#
     );
     assert_eq!(Argument::Literal(vec![]).format(), "db \"\"");
 }
 
+#[test]
+fn test_argument_format_radix() {
+    assert_eq!(Argument::Raw(255).format_radix(Radix::Hexadecimal), "#0xFF");
+    assert_eq!(Argument::Raw(10).format_radix(Radix::Binary), "#0b1010");
+    assert_eq!(Argument::Raw(15).format_radix(Radix::Octal), "#0o17");
+    assert_eq!(
+        Argument::Memory(1, Offset::Raw(16)).format_radix(Radix::Hexadecimal),
+        "[r1 + 0x10]"
+    );
+    assert_eq!(
+        Argument::Literal(vec![1, 9410051]).format_radix(Radix::Hexadecimal),
+        "db 0x1,0x8F9603"
+    );
+    assert_eq!(
+        Argument::Literal(vec![104, 105, 0xFFFF]).format_radix(Radix::Hexadecimal),
+        r#"db "hi",0xFFFF"#
+    );
+    assert_eq!(Ok(Radix::Hexadecimal), "hex".parse());
+    assert!("decimal".parse::<Radix>().is_err());
+}
+
 #[test]
 fn test_program_format() {
     let data_str = 12529907765057034586;
@@ -236,7 +502,9 @@ fn test_program_format() {
         8 => (Command::Label, [Argument::RawLabel(2184574), Argument::None]),
     };
 
-    let expected = r#"12529907765057034586: db "testing"
+    let expected = r#".data
+12529907765057034586: db "testing"
+.text
     mov r1 #1
     mov r2 #2
     mov r3 #3
@@ -248,3 +516,70 @@ fn test_program_format() {
 
     assert_eq!(expected, format_program(&program));
 }
+
+#[test]
+fn test_program_format_with_symbols() {
+    let data_str = 12529907765057034586;
+    let program = maplit::btreemap! {
+        1 => (Command::LabelledData(data_str), [Argument::Literal(vec![104, 105]), Argument::None]),
+        2 => (Command::Move, [Argument::Register(1), Argument::Raw(9)]),
+        3 => (Command::Move, [Argument::HeapDeref(data_str, 1), Argument::Raw(9)]),
+        4 => (Command::Label, [Argument::RawLabel(2184574), Argument::None]),
+        5 => (Command::Branch, [Argument::RawLabel(2184574), Argument::None]),
+    };
+    let symbols = SymbolTable {
+        labels: maplit::btreemap! {
+            data_str => String::from("data_str"),
+            2184574 => String::from("again"),
+        },
+        constants: maplit::btreemap! { String::from("SIZE") => 9 },
+        immediates: maplit::btreemap! { 2 => [None, Some(String::from("SIZE"))] },
+    };
+
+    let expected = r#".equ SIZE 9
+.data
+data_str: db "hi"
+.text
+    mov r1 #SIZE
+    mov [:data_str + 1] #9
+again:
+    b :again
+"#;
+
+    assert_eq!(
+        expected,
+        format_program_with_symbols(&program, &symbols, Radix::Decimal)
+    );
+}
+
+#[test]
+fn test_data_layout() {
+    let program = maplit::btreemap! {
+        0 => (Command::Branch, [Argument::RawLabel(1), Argument::None]),
+        1 => (Command::LabelledData(10), [Argument::Literal(vec![104, 105]), Argument::None]),
+        2 => (Command::Label, [Argument::RawLabel(1), Argument::None]),
+        3 => (Command::LabelledData(11), [Argument::Literal(vec![0; 4]), Argument::None]),
+    };
+
+    assert_eq!(
+        vec![
+            DataEntry {
+                label: 10,
+                line: 1,
+                handle: 0,
+                length: 2
+            },
+            DataEntry {
+                label: 11,
+                line: 3,
+                handle: 1,
+                length: 4
+            },
+        ],
+        data_layout(&program)
+    );
+    assert_eq!(
+        "    b :1\n.data\n10: db \"hi\"\n.text\n1:\n.data\n11: resw 4\n",
+        format_program(&program)
+    );
+}
@@ -0,0 +1,17 @@
+; prints every argument passed after `--`, output is the number of arguments
+arg: db ""
+    func :argc
+    pop r5
+    mov r0 #0
+arg_loop:
+    cmp r0 r5
+    bge :stop
+    push :arg
+    push r0
+    func :argv
+    pop r1
+    push :arg
+    func :print
+    add r0 #1
+    b :arg_loop
+stop:
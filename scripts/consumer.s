@@ -0,0 +1,11 @@
+; adds up the numbers received on channel 1 until it receives 0
+    mov r0 #0
+receive_loop:
+    push #1
+    func :recv
+    pop r1
+    cmp r1 #0
+    beq :done
+    add r0 r1
+    b :receive_loop
+done:
@@ -0,0 +1,22 @@
+; two cores increment a shared counter 1000 times each without locking,
+; the output shows how many updates were lost, try different --seed values
+    mov r2 #0
+    spawn r5 :worker
+    spawn r6 :worker
+    join r5
+    join r6
+    ld r1 [r2]
+    mov r0 #2000
+    sub r0 r1
+    hlt
+
+worker:
+    mov r1 #0
+.increment:
+    ld r3 [r2]
+    add r3 #1
+    st [r2] r3
+    add r1 #1
+    cmp r1 #1000
+    bl :.increment
+    hlt
@@ -0,0 +1,20 @@
+; counts how often it ran in the first byte of the disk, run with --disk <image>
+.equ DISK 4294914048
+.equ READ 1
+.equ WRITE 2
+    mov r1 #DISK
+    ; read sector 0
+    st [r1 + 1] #0
+    st [r1] #READ
+    ld r2 [r1]
+    cmp r2 #0
+    bne :failed
+    ld r0 [r1 + 8]
+    add r0 #1
+    mod r0 #256
+    st [r1 + 8] r0
+    ; write it back
+    st [r1] #WRITE
+    hlt
+failed:
+    exit #1
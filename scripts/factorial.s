@@ -1,5 +1,6 @@
 ; calculation of 9!
-mov r5 #9
+.equ N 9
+mov r5 #N
 mov r0 #1
 mov r1 #1
 start:
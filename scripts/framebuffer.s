@@ -0,0 +1,21 @@
+; draws coloured diagonal stripes on the framebuffer, run with --frames <dir>
+.equ FRAMEBUFFER 4294905856
+.equ WIDTH 64
+.equ PIXELS WIDTH * WIDTH
+.equ COLOURS 16
+    mov r1 #FRAMEBUFFER
+    mov r2 #0
+pixel:
+    ; colour = (x + y) / 4 % 16 where x = i % 64 and y = i / 64
+    mov r3 r2
+    mod r3 #WIDTH
+    mov r4 r2
+    div r4 #WIDTH
+    add r3 r4
+    div r3 #4
+    mod r3 #COLOURS
+    st [r1 + r2] r3
+    add r2 #1
+    cmp r2 #PIXELS
+    bl :pixel
+    flush
@@ -0,0 +1,9 @@
+; divides by zero, the fault handler sets the result to 99 and resumes after the divide
+    ivt #0 :on_divide
+    mov r0 #10
+    div r0 r1
+    hlt
+
+on_divide:
+    mov r0 #99
+    iret
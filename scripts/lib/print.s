@@ -0,0 +1,14 @@
+; macros around the print functions, include with .include "lib/print.s"
+.macro print text
+    push \text
+    func :print
+.endm
+.macro print_number register
+    push :print_buffer
+    push \register
+    func :itoa
+    print :print_buffer
+.endm
+.data
+print_buffer: db ""
+.text
@@ -0,0 +1,10 @@
+; counts down from 3 and prints go, using the macros from lib/print.s
+.include "lib/print.s"
+go: db "go"
+    mov r0 #3
+countdown:
+    print_number r0
+    sub r0 #1
+    cmp r0 #0
+    bg :countdown
+    print :go
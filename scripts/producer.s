@@ -0,0 +1,13 @@
+; sends the numbers 1 to 10 on channel 1 and 0 when done,
+; run with: run-many scripts/producer.s scripts/consumer.s
+    mov r1 #1
+send_loop:
+    push #1
+    push r1
+    func :send
+    add r1 #1
+    cmp r1 #10
+    ble :send_loop
+    push #1
+    push #0
+    func :send
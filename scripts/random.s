@@ -1,20 +1,18 @@
 data: db ""
-    mov r5 #10
+.equ RANDOM 4294901776
+.equ COUNT 10
+    ; the random device
+    mov r2 #RANDOM
+    mov r5 #COUNT
     mov r1 #0
-random_loop:
-    func :getrandom
-    add r1 #1
-    cmp r1 r5
-    bl :random_loop
-    mov r1 #0
-print_loop:
-    pop r0
+1:
+    ld r0 [r2]
     ; print random as lowercase letters
     mod r0 #26
-    add r0 #97
+    add r0 #'a'
     mov [:data] r0
     push :data
     func :print
     add r1 #1
     cmp r1 r5
-    bl :print_loop
+    bl :1b
@@ -0,0 +1,16 @@
+; echo every line of input in uppercase, output is the number of lines
+line: db ""
+    mov r0 #0
+read_loop:
+    push :line
+    func :readline
+    pop r1
+    cmp r1 #0
+    beq :stop
+    push :line
+    func :upper
+    push :line
+    func :print
+    add r0 #1
+    b :read_loop
+stop:
@@ -1,11 +1,13 @@
-mov r6 #529 ; input
+INPUT = 529
+ITERATIONS = 8
+mov r6 #INPUT
 mov r5 #0 ; counter
 mov r0 r6 ; initial value 
 mov r1 #0
 mov r2 #0
 mov r3 #0
 start:
-    cmp r5 #8
+    cmp r5 #ITERATIONS
     bge :stop
     ; 2x
     mov r2 r0
@@ -0,0 +1,26 @@
+; builds "RESULT: 720" from a computed number and prints it
+.data
+title: db "result: "
+number: db ""
+.text
+    mov r0 #1
+    mov r1 #1
+start:
+    cmp r1 #6
+    bg :stop
+    mul r0 r1
+    add r1 #1
+    b :start
+stop:
+    ; number = itoa(r0)
+    push :number
+    push r0
+    func :itoa
+    ; title = title + number
+    push :title
+    push :number
+    func :strcat
+    push :title
+    func :upper
+    push :title
+    func :print
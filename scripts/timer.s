@@ -0,0 +1,18 @@
+; counts to 1000 while the timer interrupt counts its ticks in memory,
+; the output is the number of ticks, run with --timer
+    ivt #32 :on_timer
+    ei
+    mov r1 #0
+count:
+    add r1 #1
+    cmp r1 #1000
+    bl :count
+    di
+    ld r0 [r2]
+    hlt
+
+on_timer:
+    ld r3 [r2]
+    add r3 #1
+    st [r2] r3
+    iret